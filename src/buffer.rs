@@ -1,10 +1,10 @@
 use std::{
-    error::Error, fs::{self, File}, hint, io::{stdout, BufRead, BufReader, Write}, str::FromStr
+    cmp, error::Error, fs::{self, File, OpenOptions}, fmt, io::{self, stdout, BufRead, BufReader, Write}, ops::RangeInclusive, str::FromStr
 };
 
-use crossterm::{cursor::{position, MoveRight}, execute, terminal::size};
+use crossterm::{cursor::MoveTo, execute, style::Color, terminal::{size, Clear, ClearType}};
 
-use crate::{utils, Action, History, Redo, Undo};
+use crate::{conceal, highlight, print_bg, print_fg, utils, Action, History, Language, Redo, RunError, Selection, SelectionKind, Settings, Span, Undo};
 
 #[derive(Clone)]
 enum Edit {
@@ -15,35 +15,124 @@ enum Edit {
     DeleteLine  {row: usize, deleted: Vec<char>},
 }
 
+// What ends each line in the file, kept so saving doesn't change it
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum LineEnding {
+    #[default]
+    Unix,
+    Dos,
+}
+
+impl LineEnding {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Unix => "\n",
+            Self::Dos => "\r\n",
+        }
+    }
+}
+
+// What's highlighted over the text when it's drawn. `bracket` is the absolute position of a bracket
+// to highlight as matching the cursor's, and `search` a pattern whose matches are highlighted.
+#[derive(Default)]
+pub struct Overlay<'a> {
+    pub selection: Option<&'a Selection>,
+    pub bracket: Option<(usize, usize)>,
+    pub search: Option<&'a [char]>,
+}
+
 pub struct Buffer {
     pub path: Option<String>,
     pub modified: bool,
 
+    // History depth at the last save, None once that state can no longer be reached by undo/redo
+    saved_at: Option<usize>,
+
+    // Set by options that change how the file is written without touching the history, so undo
+    // can't bring the buffer back to the saved state until it's written again
+    format_changed: bool,
+
+    // The last line edited and its contents before that run of edits started, restored by `U`
+    line_snapshot: Option<(usize, Vec<char>)>,
+
+    // Scratch buffers are throwaway, so quitting never complains about their changes
+    pub scratch: bool,
+
+    // The built-in help shown by :help
+    pub help: bool,
+
+    // Whether the content may change at all, regardless of whether it can be written
+    pub modifiable: bool,
+
+    // Read-only buffers can't be edited and are only written over with :w!
+    pub readonly: bool,
+
+    // Opened from a file with NUL bytes or invalid UTF-8, which is shown with those replaced
+    pub binary: bool,
+
+    pub line_ending: LineEnding,
+
+    // Whether the last line ends with a line ending too, as it did in the file. With fixeol
+    // on, one is always written at the end.
+    pub final_newline: bool,
+    pub fixeol: bool,
+
+    // Language the lines are highlighted as, if any
+    pub syntax: Option<Language>,
+
     data: Vec<Vec<char>>,
     pub start: usize,
 
+    // First screen column shown, scrolled right when the cursor goes past the window edge
+    pub left: usize,
+
+    // Absolute rows changed since the last print, and the first row everything below is stale from
+    dirty: Vec<usize>,
+    dirty_from: Option<usize>,
+
     history: History<Edit>,
 }
 
-impl FromStr for Buffer {
-    type Err = Box<dyn Error>;
-
-    fn from_str(path: &str) -> Result<Self, Self::Err> {
-        let file = File::open(path)?;
-        let reader = BufReader::new(file);
+// Why a file couldn't be read into a buffer
+#[derive(Debug)]
+pub enum OpenError {
+    NotFound,
+    PermissionDenied,
+    Other(io::Error),
+}
 
-        let mut buffer_data = Vec::new();
-        for line_result in reader.lines() {
-            let line = line_result.unwrap().chars().collect::<Vec<char>>();
-            buffer_data.push(line);
+impl From<io::Error> for OpenError {
+    fn from(e: io::Error) -> Self {
+        match e.kind() {
+            io::ErrorKind::NotFound => Self::NotFound,
+            io::ErrorKind::PermissionDenied => Self::PermissionDenied,
+            _ => Self::Other(e),
         }
+    }
+}
 
-        // Start the buffer off with a single empty line if the opened file has no lines
-        if buffer_data.is_empty() {
-            buffer_data.push(vec![]);
+impl fmt::Display for OpenError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::NotFound => write!(f, "no such file"),
+            Self::PermissionDenied => write!(f, "permission denied"),
+            Self::Other(e) => write!(f, "{}", e),
         }
+    }
+}
+
+impl Error for OpenError {}
+
+impl FromStr for Buffer {
+    type Err = OpenError;
+
+    fn from_str(path: &str) -> Result<Self, Self::Err> {
+        let file = File::open(path)?;
+        let mut buffer = Buffer::from_reader(Some(path.to_string()), BufReader::new(file))?;
 
-        Ok(Buffer::from(path.to_string(), buffer_data))
+        // Files we aren't allowed to write to start out read-only
+        buffer.readonly |= fs::metadata(path).is_ok_and(|metadata| metadata.permissions().readonly());
+        Ok(buffer)
     }
 }
 
@@ -51,23 +140,49 @@ impl Default for Buffer {
     fn default() -> Self {
         Self { 
             modified: false,
+            saved_at: Some(0),
+            format_changed: false,
+            line_snapshot: None,
+            scratch: false,
+            help: false,
+            modifiable: true,
+            readonly: false,
+            binary: false,
+            line_ending: LineEnding::Unix,
+            final_newline: true,
+            fixeol: false,
+            syntax: None,
             start: 0,
+            left: 0,
             path: None,
             data: vec![vec![]],
+            dirty: vec![],
+            dirty_from: None,
             history: History::new(),
         }
     }
 }
 
 impl Undo for Buffer {
+    // Undoes every edit in the most recent group, so one change comes back in one step
     fn undo(&mut self) {
-        if let Some(edit) = self.history.last_from(Action::Undo) {
+        let group = self.history.last_group(Action::Undo);
+
+        while let Some(edit) = self.history.last_from(Action::Undo)
+            && self.history.last_group(Action::Undo) == group
+        {
+            let depth = self.history.depth();
             match edit {
-                Edit::InsertChar { row, col, c: _ }                      => self.delete_char(row, col, Action::Undo),
-                Edit::DeleteChar { row, col, deleted }             => self.insert_char(row, col,deleted, Action::Undo),
-                Edit::SetLine    { row, old_line, new_line: _ }      => self.set_line(row, old_line, Action::Undo),
-                Edit::InsertLine { row, line: _ }                               => self.delete_line(row, Action::Undo),
-                Edit::DeleteLine { row, deleted }                    => self.insert_line(row, deleted, Action::Undo),
+                Edit::InsertChar { row, col, c: _ }                      => self.delete_char_at(row, col, Action::Undo),
+                Edit::DeleteChar { row, col, deleted }             => self.insert_char_at(row, col,deleted, Action::Undo),
+                Edit::SetLine    { row, old_line, new_line: _ }      => self.set_line_at(row, old_line, Action::Undo),
+                Edit::InsertLine { row, line: _ }                               => self.delete_line_at(row, Action::Undo),
+                Edit::DeleteLine { row, deleted }                    => self.insert_line_at(row, deleted, Action::Undo),
+            }
+
+            // Stop rather than spin if the edit couldn't be taken back
+            if self.history.depth() == depth {
+                break;
             }
         }
     }
@@ -75,122 +190,384 @@ impl Undo for Buffer {
 
 impl Redo for Buffer {
     fn redo(&mut self) {
-        if let Some(edit) = self.history.last_from(Action::Redo) {
+        let group = self.history.last_group(Action::Redo);
+
+        while let Some(edit) = self.history.last_from(Action::Redo)
+            && self.history.last_group(Action::Redo) == group
+        {
+            let depth = self.history.depth();
             match edit {
-                Edit::InsertChar { row, col, c }                  => self.insert_char(row, col, c, Action::Redo),
-                Edit::DeleteChar { row, col, deleted: _ }               => self.delete_char(row, col, Action::Redo),
-                Edit::SetLine    { row, old_line: _, new_line }     => self.set_line(row, new_line, Action::Redo),
-                Edit::InsertLine { row, line }                      => self.insert_line(row, line, Action::Redo),
-                Edit::DeleteLine { row, deleted: _ }                           => self.delete_line(row, Action::Redo),
+                Edit::InsertChar { row, col, c }                  => self.insert_char_at(row, col, c, Action::Redo),
+                Edit::DeleteChar { row, col, deleted: _ }               => self.delete_char_at(row, col, Action::Redo),
+                Edit::SetLine    { row, old_line: _, new_line }     => self.set_line_at(row, new_line, Action::Redo),
+                Edit::InsertLine { row, line }                      => self.insert_line_at(row, line, Action::Redo),
+                Edit::DeleteLine { row, deleted: _ }                           => self.delete_line_at(row, Action::Redo),
+            }
+
+            if self.history.depth() == depth {
+                break;
             }
         }
     }
 }
 
 impl Buffer {
+    // Applies buffer-local options from :set, leaving everything else to the editor's Settings
+    pub fn set_option(&mut self, option: &str) -> Result<(), RunError> {
+        if let Some(format) = option.strip_prefix("fileformat=") {
+            let line_ending = match format {
+                "unix" => LineEnding::Unix,
+                "dos" => LineEnding::Dos,
+                _ => return Err(RunError::InvalidValue),
+            };
+
+            // The file on disk no longer matches, so it needs saving again
+            if line_ending != self.line_ending {
+                self.line_ending = line_ending;
+                self.format_changed = true;
+                self.modified = true;
+            }
+            return Ok(());
+        }
+
+        if let Some(name) = option.strip_prefix("syntax=") {
+            self.syntax = match name {
+                "" | "off" => None,
+                name => Some(Language::from_name(name).ok_or(RunError::InvalidValue)?),
+            };
+            return Ok(());
+        }
+
+        let enabled = !option.starts_with("no");
+
+        match option {
+            "modifiable" | "nomodifiable" => self.modifiable = enabled,
+            "readonly" | "noreadonly" => self.readonly = enabled,
+            "fixeol" | "nofixeol" => {
+                // Only a file missing its last line ending is written any differently
+                if enabled != self.fixeol && !self.final_newline {
+                    self.format_changed = true;
+                    self.modified = true;
+                }
+                self.fixeol = enabled;
+            }
+            _ => return Err(RunError::UnknownOption),
+        }
+
+        Ok(())
+    }
+
     pub fn new(path: Option<String>) -> Self {
-        let mut new_buffer = Buffer::default();
-        new_buffer.path = path;
-        new_buffer
+        let syntax = path.as_deref().and_then(Language::from_path);
+        Self { path, syntax, ..Default::default() }
     }
 
-    fn from(path: String, data: Vec<Vec<char>>) -> Self {
-        let mut new_buffer = Buffer::default();
-        new_buffer.path = Some(path);
-        new_buffer.data = data;
-        new_buffer
+    pub fn scratch() -> Self {
+        Self { scratch: true, ..Default::default() }
     }
 
-    pub fn move_down(&mut self) -> bool {
-        if self.length() != 0 {
-            if self.start + (position().unwrap().1 as usize) < self.length() - 1 {
-                self.start += 1;
-                return true;
-            }
+    // The help text, which can be read but not changed or written anywhere
+    pub fn help(text: &str) -> Self {
+        let data = text.lines().map(|line| line.chars().collect()).collect();
+        Self { data, help: true, scratch: true, modifiable: false, readonly: true, ..Default::default() }
+    }
+
+    // What the buffer is called on screen
+    pub fn name(&self) -> &str {
+        match (&self.path, self.help) {
+            (Some(path), _) => path,
+            (None, true) => "[Help]",
+            (None, false) => "[No Name]",
         }
-        return false;
     }
 
-    pub fn move_up(&mut self) -> bool {
-        if self.length() != 0 {
-            if self.start > 0 {
-                self.start -= 1;
-                return true;
-            }
+    // Reads a buffer from anything line based, like an opened file or piped stdin. Binary files
+    // and ones that aren't valid UTF-8 still open, as best they can be shown, but read-only since
+    // saving would write them back changed.
+    pub fn from_reader<R: BufRead>(path: Option<String>, mut reader: R) -> Result<Self, OpenError> {
+        let mut data = Vec::new();
+        let mut binary = false;
+
+        // Lines are read with their endings so the file can be saved with whichever it mostly uses
+        let (mut unix, mut dos) = (0, 0);
+        let mut final_newline = false;
+        let mut bytes = Vec::new();
+        while reader.read_until(b'\n', &mut bytes)? > 0 {
+            final_newline = bytes.ends_with(b"\n");
+            let content = if let Some(rest) = bytes.strip_suffix(b"\r\n") {
+                dos += 1;
+                rest
+            } else if let Some(rest) = bytes.strip_suffix(b"\n") {
+                unix += 1;
+                rest
+            } else {
+                &bytes[..]
+            };
+
+            let line = match std::str::from_utf8(content) {
+                Ok(line) if !line.contains('\0') => line.chars().collect(),
+                _ => {
+                    binary = true;
+                    String::from_utf8_lossy(content).chars().collect()
+                }
+            };
+            data.push(line);
+            bytes.clear();
+        }
+
+        // Start the buffer off with a single empty line if there are no lines
+        if data.is_empty() {
+            data.push(vec![]);
         }
-        return false;
+
+        let line_ending = if dos > unix { LineEnding::Dos } else { LineEnding::Unix };
+        let syntax = path.as_deref().and_then(Language::from_path);
+        Ok(Self { path, data, line_ending, final_newline, syntax, binary, readonly: binary, ..Default::default() })
     }
-    
 
     pub fn length(&self) -> usize {
         self.data.len()
     }
 
+    // A buffer never has zero lines, so "empty" means it holds a single empty line
+    pub fn is_empty(&self) -> bool {
+        self.data.len() == 1 && self.data[0].is_empty()
+    }
+
     pub fn bytes(&self) -> Vec<u8> {
-        let mut bytes = Vec::new();
+        // Collecting into a String keeps multi-byte chars intact
+        let mut contents = String::new();
         for (i, line) in self.data.iter().enumerate() {
-            for char in line {
-                bytes.push(*char as u8)
-            }
+            contents.extend(line);
 
-            if i != self.data.len() {
-                bytes.push(b'\n');
+            // Newlines go between lines, and after the last one only if the file had one there
+            if i != self.data.len() - 1 || self.final_newline || self.fixeol {
+                contents.push_str(self.line_ending.as_str());
             }
         }
-        bytes
+        contents.into_bytes()
     }
 
     pub fn write(&self) -> Result<(), std::io::Error> {
         let path = self
             .path
             .as_ref()
-            .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::Other, "Path is empty"))?;
+            .ok_or_else(|| std::io::Error::other("Path is empty"))?;
 
         fs::write(path, self.bytes())
     }
 
-    pub fn print(&self) {
-        for i in self.start..(self.start + utils::window_size() as usize + 1) {
-            if let Some(line) = self.data.get(i) {
-                for char in line {
-                    print!("{}", char);
+    // Writes some of the buffer's lines to another file, each terminated by a newline
+    pub fn write_lines(&self, path: &str, lines: RangeInclusive<usize>, append: bool) -> Result<(), std::io::Error> {
+        let mut contents = String::new();
+        for line in &self.data[lines] {
+            contents.extend(line);
+            contents.push_str(self.line_ending.as_str());
+        }
+
+        OpenOptions::new()
+            .write(true)
+            .append(append)
+            .truncate(!append)
+            .create(!append)
+            .open(path)?
+            .write_all(contents.as_bytes())
+    }
+
+    pub fn print(&mut self, settings: &Settings, cursor_row: u16, overlay: &Overlay) {
+        // Wrapped lines can take up several rows each, so rows are counted rather than lines
+        let height = utils::window_size() as usize + 1;
+        let mut screen_row = 0;
+        for i in self.start..self.data.len() {
+            if screen_row >= height {
+                break;
+            }
+
+            execute!(stdout(), MoveTo(0, utils::window_top() + screen_row as u16)).unwrap();
+            screen_row += self.print_row(i, settings, cursor_row, overlay, height - screen_row);
+        }
+        self.dirty.clear();
+        self.dirty_from = None;
+
+        // Make sure all print!() output shows up before exiting
+        stdout().flush().unwrap();
+    }
+
+    // Redraws only the visible rows edited since the last print
+    pub fn print_dirty(&mut self, settings: &Settings, cursor_row: u16, overlay: &Overlay) {
+        let end = self.start + utils::window_size() as usize + 1;
+        let from = self.dirty_from.take().unwrap_or(end);
+
+        let mut rows: Vec<usize> = self.dirty.drain(..).filter(|row| *row < from).collect();
+        rows.extend(from..end);
+
+        for i in rows {
+            if i < self.start || i >= end {
+                continue;
+            }
+
+            execute!(stdout(), MoveTo(0, utils::window_top() + (i - self.start) as u16), Clear(ClearType::CurrentLine)).unwrap();
+            self.print_row(i, settings, cursor_row, overlay, 1);
+        }
+
+        stdout().flush().unwrap();
+    }
+
+    // Marks an absolute row to be redrawn on the next render
+    pub fn mark_dirty(&mut self, row: usize) {
+        self.dirty.push(row);
+    }
+
+    // Inserting or deleting a line shifts every row below it, so they all need redrawing
+    fn mark_dirty_from(&mut self, row: usize) {
+        self.dirty_from = Some(self.dirty_from.map_or(row, |from| cmp::min(from, row)));
+    }
+
+    // Screen rows a line takes up, more than one only when it wraps. A line that exactly fills
+    // its last row still gets one more, so there's somewhere to put the cursor past its end.
+    pub fn line_height(&self, row: usize, settings: &Settings, width: usize) -> usize {
+        match self.data.get(row) {
+            Some(line) if settings.wrap => utils::visual_col(line, line.len(), settings.tabstop) / cmp::max(width, 1) + 1,
+            _ => 1,
+        }
+    }
+
+    // Draws a line from where the cursor is, on no more than `rows` screen rows. Returns how
+    // many it took.
+    fn print_row(&self, i: usize, settings: &Settings, cursor_row: u16, overlay: &Overlay, rows: usize) -> usize {
+        let Some(line) = self.data.get(i) else {
+            return 0;
+        };
+
+        let conceal = settings.conceallevel > 0 && self.path.as_deref().is_some_and(conceal::has_conceal_rules);
+
+        let gutter = self.gutter_width(settings) as usize;
+        let width = (size().unwrap().0 as usize).saturating_sub(gutter);
+
+        // Wrapped lines are never scrolled sideways, each row carrying on where the last left off
+        let (left, height) = match settings.wrap {
+            true => (0, cmp::min(self.line_height(i, settings, width), rows)),
+            false => (self.left, 1),
+        };
+
+        // Only draw what fits on screen so huge lines (minified files) stay cheap to render. Every
+        // char takes at least a column, so nothing past this many can be in view.
+        let selected = overlay.selection.and_then(|selection| selection.cols(i, line.len()));
+        let line = &line[..cmp::min(line.len(), left + height * width)];
+
+        // The cursor line is never concealed so editing it still shows the real characters, and
+        // neither are selected lines since concealing would shift the highlight
+        let concealed = conceal && i != self.start + cursor_row as usize && selected.is_none();
+        let (text, backgrounds, spans) = match concealed {
+            true => (conceal::conceal_line(line, settings.conceallevel), Vec::new(), Vec::new()),
+            false => {
+                // Earlier backgrounds win where they overlap, so the selection shows over everything
+                let mut backgrounds = Vec::new();
+                if let Some(range) = selected.clone() {
+                    backgrounds.push(Span { range, color: Color::DarkGrey });
+                }
+                if let Some((_, col)) = overlay.bracket.filter(|bracket| bracket.0 == i) {
+                    backgrounds.push(Span { range: col..col + 1, color: Color::DarkCyan });
+                }
+                if let Some(pattern) = overlay.search {
+                    let mut from = 0;
+                    let ignore_case = settings.ignore_case(pattern);
+                    while let Some(col) = utils::find_chars(line, pattern, from, ignore_case) {
+                        backgrounds.push(Span { range: col..col + pattern.len(), color: Color::DarkYellow });
+                        from = col + pattern.len();
+                    }
                 }
+
+                let spans = self.syntax.map(|language| highlight::highlight_line(line, language)).unwrap_or_default();
+                (line.to_vec(), backgrounds, spans)
             }
+        };
 
-            if i != self.data.len() - 1 {
+        for segment in 0..height {
+            // Only the first row of a wrapped line is numbered
+            if segment > 0 {
                 println!();
+                print!("{}", " ".repeat(gutter));
+            } else if gutter > 0 {
+                print_fg!(Color::DarkGrey, "{:>width$} ", i + 1, width = gutter - 1);
+            }
+
+            let drawn = self.print_line(&text, settings, left + segment * width, width, &backgrounds, &spans);
+
+            // Line-wise selections are highlighted right across the screen, past the end of the text
+            if selected.is_some() && overlay.selection.is_some_and(|selection| selection.kind == SelectionKind::Line) {
+                print_bg!(Color::DarkGrey, "{}", " ".repeat(width.saturating_sub(drawn)));
             }
         }
+        height
+    }
 
-        // Make sure all print!() output shows up before exiting
-        stdout().flush().unwrap();
+    // Columns taken up by line numbers and the space after them, growing with the line count
+    pub fn gutter_width(&self, settings: &Settings) -> u16 {
+        match settings.number {
+            true => cmp::max(3, self.data.len().to_string().len()) as u16 + 1,
+            false => 0,
+        }
+    }
+
+    // `backgrounds` and `spans` are the chars drawn on a colored background and in a colored
+    // foreground. Draws `width` columns starting `left` columns in, returning how many it took up.
+    fn print_line(&self, line: &[char], settings: &Settings, left: usize, width: usize, backgrounds: &[Span], spans: &[Span]) -> usize {
+        let indent = match settings.showindentlevel {
+            true => line.iter().take_while(|c| **c == ' ').count(),
+            false => 0,
+        };
+
+        // Screen column, which runs ahead of the char index once a tab has been expanded
+        let mut visual = 0;
+        for (col, char) in line.iter().enumerate() {
+            let char_width = match char {
+                '\t' => settings.tabstop - visual % settings.tabstop,
+                _ => 1,
+            };
+            if visual + char_width > left + width {
+                break;
+            }
+            visual += char_width;
+
+            // Chars scrolled off to the left are skipped, apart from what's left of a tab over the edge
+            if visual <= left {
+                continue;
+            }
+            let text = match char {
+                '\t' => " ".repeat(cmp::min(char_width, visual - left)),
+                _ => char.to_string(),
+            };
+
+            if let Some(background) = backgrounds.iter().find(|span| span.range.contains(&col)) {
+                print_bg!(background.color, "{}", text);
+            } else if col < indent && col.is_multiple_of(settings.shiftwidth) {
+                // Guides are drawn over the spaces at each indent stop so no columns shift
+                print_fg!(Color::DarkGrey, "│");
+            } else if let Some(span) = spans.iter().find(|span| span.range.contains(&col)) {
+                print_fg!(span.color, "{}", text);
+            } else {
+                print!("{}", text);
+            }
+        }
+        visual.saturating_sub(left)
     }
 
     pub fn get_line(&self, line: usize) -> Option<&Vec<char>> {
         self.data.get(line + self.start)
     }
 
-    pub fn set_line(&mut self, line: usize, new_line: Vec<char>, action: Action) {
-        if let Some(old_line) = self.data.get_mut(line + self.start) {
-            self.modified = true;
-            let edit = Edit::SetLine { row: line, old_line: old_line.clone(), new_line: new_line.clone() };
-            self.history.update(edit, action);
+    // Like get_line, but indexed from the top of the file rather than the top of the screen
+    pub fn get_line_at(&self, row: usize) -> Option<&Vec<char>> {
+        self.data.get(row)
+    }
 
-            *old_line = new_line;
-            self.modified = true;
-        }
+    pub fn set_line(&mut self, line: usize, new_line: Vec<char>, action: Action) {
+        self.set_line_at(line + self.start, new_line, action);
     }
 
     pub fn insert_line(&mut self, row: usize, line: Vec<char>, action: Action) {
-        if row < self.data.len() {
-            self.history.update(Edit::InsertLine { row: row + self.start, line: line.clone() }, action);
-            self.data.insert(row + self.start, line);
-        } else {
-            self.history.update(Edit::InsertLine { row, line: line.clone() }, action);
-            self.data.push(line);
-        }
-
-        self.modified = true;
+        self.insert_line_at(row + self.start, line, action);
     }
 
     pub fn get_line_mut(&mut self, line: usize) -> Option<&mut Vec<char>> {
@@ -202,38 +579,528 @@ impl Buffer {
     }
 
     pub fn delete_char(&mut self, row: usize, col: usize, action: Action) {
-        if let Some(line) = self.data.get_mut(row + self.start) {
-            if col < line.len() {
-                let deleted = line.remove(col);
+        self.delete_char_at(row + self.start, col, action);
+    }
 
-                self.modified = true;
-                let edit = Edit::DeleteChar { row, col, deleted };
-                self.history.update(edit, action);
-            }
+    pub fn insert_char(&mut self, row: usize, col: usize, c: char, action: Action) {
+        self.insert_char_at(row + self.start, col, c, action);
+    }
+
+    // The text from `start` up to `end`, split into lines wherever it crosses a line break.
+    // Positions are absolute (row, col) pairs, and `end` isn't included.
+    pub fn text(&self, start: (usize, usize), end: (usize, usize)) -> Vec<Vec<char>> {
+        let (start_row, start_col) = start;
+        let (end_row, end_col) = end;
+        let start_col = cmp::min(start_col, self.data[start_row].len());
+        let end_col = cmp::min(end_col, self.data[end_row].len());
+
+        if start_row == end_row {
+            return vec![self.data[start_row][start_col..cmp::max(start_col, end_col)].to_vec()];
         }
+
+        let mut lines = vec![self.data[start_row][start_col..].to_vec()];
+        lines.extend(self.data[start_row + 1..end_row].iter().cloned());
+        lines.push(self.data[end_row][..end_col].to_vec());
+        lines
     }
 
-    pub fn insert_char(&mut self, row: usize, col: usize, c: char, action: Action) {
-        if let Some(line) = self.data.get_mut(row + self.start) {
-            if col > line.len() {
-                line.push(c);
-            } else {
-                line.insert(col, c);
+    // Removes the text from `start` up to `end`, joining what's left of the first and last lines
+    pub fn delete_text(&mut self, start: (usize, usize), end: (usize, usize)) {
+        let (start_row, start_col) = start;
+        let (end_row, end_col) = end;
+        let start_col = cmp::min(start_col, self.data[start_row].len());
+        let end_col = cmp::max(start_col, cmp::min(end_col, self.data[end_row].len()));
+
+        let mut joined = self.data[start_row][..start_col].to_vec();
+        joined.extend_from_slice(&self.data[end_row][end_col..]);
+
+        for _ in start_row..end_row {
+            self.delete_line_at(start_row + 1, Action::Do);
+        }
+        self.set_line_at(start_row, joined, Action::Do);
+    }
+
+    // Puts text in at an absolute position, the opposite of delete_text
+    pub fn insert_text(&mut self, pos: (usize, usize), lines: Vec<Vec<char>>) {
+        let (row, col) = pos;
+        let Some(line) = self.data.get(row) else {
+            return;
+        };
+
+        let col = cmp::min(col, line.len());
+        let tail = line[col..].to_vec();
+        let mut first = line[..col].to_vec();
+
+        let mut lines = lines.into_iter();
+        first.extend(lines.next().unwrap_or_default());
+        let mut rest: Vec<Vec<char>> = lines.collect();
+
+        // The rest of the line the text went into ends up after its last line
+        match rest.last_mut() {
+            Some(last) => last.extend(tail),
+            None => first.extend(tail),
+        }
+
+        self.set_line_at(row, first, Action::Do);
+        for (i, line) in rest.into_iter().enumerate() {
+            self.insert_line_at(row + 1 + i, line, Action::Do);
+        }
+    }
+
+    // Where the bracket at an absolute position is closed or opened, skipping over brackets of the
+    // same kind nested inside. None if it isn't on a bracket or the bracket is never matched.
+    pub fn matching_bracket(&self, pos: (usize, usize)) -> Option<(usize, usize)> {
+        let c = *self.data.get(pos.0)?.get(pos.1)?;
+
+        if let Some(closing) = utils::closeable(c).filter(|closing| utils::braces(c, *closing)) {
+            let mut depth = 0;
+            for row in pos.0..self.data.len() {
+                let from = if row == pos.0 { pos.1 } else { 0 };
+                for (col, other) in self.data[row].iter().enumerate().skip(from) {
+                    if *other == c {
+                        depth += 1;
+                    } else if *other == closing {
+                        depth -= 1;
+                        if depth == 0 {
+                            return Some((row, col));
+                        }
+                    }
+                }
+            }
+        } else if let Some(opening) = utils::openeable(c).filter(|opening| utils::braces(*opening, c)) {
+            let mut depth = 0;
+            for row in (0..=pos.0).rev() {
+                let to = if row == pos.0 { pos.1 + 1 } else { self.data[row].len() };
+                for (col, other) in self.data[row][..to].iter().enumerate().rev() {
+                    if *other == c {
+                        depth += 1;
+                    } else if *other == opening {
+                        depth -= 1;
+                        if depth == 0 {
+                            return Some((row, col));
+                        }
+                    }
+                }
             }
+        }
+        None
+    }
+
+    pub fn delete_line(&mut self, row: usize, action: Action) {
+        self.delete_line_at(row + self.start, action);
+    }
+
+    // The *_at variants take absolute rows into `data`. Every edit is recorded with absolute
+    // rows so undo and redo land on the right line no matter how far the view has scrolled since.
+    fn delete_char_at(&mut self, row: usize, col: usize, action: Action) {
+        if !self.modifiable {
+            return;
+        }
+        self.snapshot_line(row, action);
+        if let Some(line) = self.data.get_mut(row)
+            && col < line.len()
+        {
+            let deleted = line.remove(col);
+
+            let edit = Edit::DeleteChar { row, col, deleted };
+            self.mark_dirty(row);
+            self.record(edit, action);
+        }
+    }
+
+    fn insert_char_at(&mut self, row: usize, col: usize, c: char, action: Action) {
+        if !self.modifiable {
+            return;
+        }
+        self.snapshot_line(row, action);
+        if let Some(line) = self.data.get_mut(row) {
+            // Record the column the char actually landed on so undo deletes the right one
+            let col = cmp::min(col, line.len());
+            line.insert(col, c);
 
-            self.modified = true;
             let edit = Edit::InsertChar { row, col, c };
-            self.history.update(edit, action);
+            self.mark_dirty(row);
+            self.record(edit, action);
         }
     }
 
-    pub fn delete_line(&mut self, row: usize, action: Action) {
+    pub fn set_line_at(&mut self, row: usize, new_line: Vec<char>, action: Action) {
+        if !self.modifiable {
+            return;
+        }
+        self.snapshot_line(row, action);
+        if let Some(old_line) = self.data.get_mut(row) {
+            let edit = Edit::SetLine { row, old_line: old_line.clone(), new_line: new_line.clone() };
+
+            *old_line = new_line;
+            self.mark_dirty(row);
+            self.record(edit, action);
+        }
+    }
+
+    pub fn insert_line_at(&mut self, row: usize, line: Vec<char>, action: Action) {
+        if !self.modifiable {
+            return;
+        }
+        let row = cmp::min(row, self.data.len());
+
+        // Rows shift around the inserted line, so the snapshot might not point at its line anymore
+        self.line_snapshot = None;
+        self.data.insert(row, line.clone());
+        self.mark_dirty_from(row);
+        self.record(Edit::InsertLine { row, line }, action);
+    }
+
+    pub fn delete_line_at(&mut self, row: usize, action: Action) {
+        if !self.modifiable {
+            return;
+        }
         if row < self.data.len() {
-            let deleted = self.data.get(row).expect("Deleted line does not exist");
-            
-            self.history.update(Edit::DeleteLine { row: row + self.start, deleted: deleted.clone()}, action);
-            self.data.remove(row + self.start);
-            self.modified = true;
+            let deleted = self.data.remove(row);
+            self.line_snapshot = None;
+            self.mark_dirty_from(row);
+
+            self.record(Edit::DeleteLine { row, deleted }, action);
+        }
+    }
+
+    // Restores the last edited line to how it was before the latest run of edits on it. This is an
+    // edit itself, so `u` takes it back and using it twice in a row undoes the first one.
+    pub fn undo_line(&mut self) -> bool {
+        let Some((row, original)) = self.line_snapshot.take() else {
+            return false;
+        };
+        let Some(current) = self.data.get(row).cloned() else {
+            return false;
+        };
+
+        self.set_line_at(row, original, Action::Do);
+        self.line_snapshot = Some((row, current));
+        true
+    }
+
+    fn snapshot_line(&mut self, row: usize, action: Action) {
+        let Action::Do = action else {
+            return;
+        };
+
+        if self.line_snapshot.as_ref().is_none_or(|(snapshot_row, _)| *snapshot_row != row)
+            && let Some(line) = self.data.get(row)
+        {
+            self.line_snapshot = Some((row, line.clone()));
+        }
+    }
+
+    pub fn begin_group(&mut self, group: usize) {
+        self.history.begin_group(group);
+    }
+
+    // The group of edits the next undo or redo would take back
+    pub fn last_group(&self, action: Action) -> Option<usize> {
+        self.history.last_group(action)
+    }
+
+    pub fn history_groups(&self) -> usize {
+        self.history.groups()
+    }
+
+    // Edits between the current state and the last save, None if the saved state is unreachable
+    pub fn unsaved_changes(&self) -> Option<usize> {
+        self.saved_at.map(|saved_at| saved_at.abs_diff(self.history.depth()))
+    }
+
+    // True when undo and redo have brought the buffer back to what was last written
+    pub fn is_at_saved_state(&self) -> bool {
+        self.saved_at == Some(self.history.depth())
+    }
+
+    pub fn mark_saved(&mut self) {
+        self.saved_at = Some(self.history.depth());
+        self.format_changed = false;
+        self.modified = false;
+    }
+
+    fn record(&mut self, edit: Edit, action: Action) {
+        // A fresh edit made from below the saved point makes the saved state unreachable
+        if let (Action::Do, Some(saved_at)) = (action, self.saved_at)
+            && self.history.depth() < saved_at
+        {
+            self.saved_at = None;
+        }
+
+        let dropped = self.history.update(edit, action);
+        self.forget_oldest(dropped);
+        self.modified = self.format_changed || !self.is_at_saved_state();
+    }
+
+    // Negative undolevels disable undo entirely, and 0 still keeps the one change, like vim
+    pub fn set_undolevels(&mut self, undolevels: i64) {
+        let limit = match undolevels {
+            ..0 => 0,
+            levels => cmp::max(levels, 1) as usize,
+        };
+        let dropped = self.history.set_limit(Some(limit));
+        self.forget_oldest(dropped);
+    }
+
+    fn forget_oldest(&mut self, dropped: usize) {
+        // The saved point shifts down with the history, and is lost for good if it was dropped
+        if dropped > 0 {
+            self.saved_at = self.saved_at.and_then(|saved_at| saved_at.checked_sub(dropped));
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(bytes: &[u8]) -> Buffer {
+        Buffer::from_reader(None, bytes).unwrap()
+    }
+
+    fn lines(buffer: &Buffer) -> Vec<String> {
+        (0..buffer.length()).map(|row| buffer.get_line_at(row).unwrap().iter().collect()).collect()
+    }
+
+    // Makes an edit with the view scrolled down, scrolls back to the top and undoes it, which has
+    // to land on the line that was edited rather than the one at the same screen row
+    fn undo_after_scrolling(edit: impl FnOnce(&mut Buffer), edited: &[&str]) {
+        let mut buffer = read(b"0\n1\n2\n3\n4\n5\n6\n7\n");
+        let original = lines(&buffer);
+
+        buffer.start = 4;
+        buffer.begin_group(1);
+        edit(&mut buffer);
+        assert_eq!(lines(&buffer), edited);
+
+        buffer.start = 0;
+        buffer.undo();
+        assert_eq!(lines(&buffer), original);
+        buffer.redo();
+        assert_eq!(lines(&buffer), edited);
+    }
+
+    #[test]
+    fn bytes_keep_multibyte_chars() {
+        let original = "café\n→ 🦀\n".as_bytes();
+        assert_eq!(read(original).bytes(), original);
+    }
+
+    #[test]
+    fn write_round_trips_unchanged() {
+        let original = "café\n→\n".as_bytes();
+        let path = std::env::temp_dir().join(format!("vision-write-{}", std::process::id()));
+        let path = path.to_str().unwrap().to_string();
+
+        let mut buffer = read(original);
+        buffer.path = Some(path.clone());
+        buffer.write().unwrap();
+        let written = fs::read(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(written, original);
+    }
+
+    #[test]
+    fn bytes_only_join_lines() {
+        assert_eq!(read(b"a\nb").bytes(), b"a\nb");
+        assert_eq!(read(b"a\nb\n").bytes(), b"a\nb\n");
+        assert_eq!(read(b"").bytes(), b"");
+    }
+
+    #[test]
+    fn saving_again_changes_nothing() {
+        for original in [&b"a\nb"[..], b"a\nb\n", b"a\n\n"] {
+            let once = read(original).bytes();
+            assert_eq!(read(&once).bytes(), once);
+            assert_eq!(once, original);
+        }
+    }
+
+    #[test]
+    fn crlf_round_trips() {
+        let buffer = read(b"a\r\nb\r\n");
+        assert_eq!(buffer.line_ending, LineEnding::Dos);
+        assert_eq!(buffer.get_line_at(0), Some(&vec!['a']));
+        assert_eq!(buffer.bytes(), b"a\r\nb\r\n");
+    }
+
+    #[test]
+    fn mostly_used_ending_wins() {
+        let buffer = read(b"a\r\nb\r\nc\n");
+        assert_eq!(buffer.line_ending, LineEnding::Dos);
+        assert_eq!(buffer.bytes(), b"a\r\nb\r\nc\r\n");
+        assert_eq!(read(b"a\nb\r\nc\n").line_ending, LineEnding::Unix);
+    }
+
+    #[test]
+    fn fileformat_overrides_the_ending() {
+        let mut buffer = read(b"a\r\nb\r\n");
+        buffer.set_option("fileformat=unix").unwrap();
+        assert_eq!(buffer.bytes(), b"a\nb\n");
+    }
+
+    #[test]
+    fn missing_final_newline_is_kept() {
+        let buffer = read(b"a\nb");
+        assert!(!buffer.final_newline);
+        assert_eq!(buffer.bytes(), b"a\nb");
+    }
+
+    #[test]
+    fn fixeol_adds_final_newline() {
+        let mut buffer = read(b"a\nb");
+        buffer.set_option("fixeol").unwrap();
+        assert!(buffer.modified);
+        assert_eq!(buffer.bytes(), b"a\nb\n");
+        assert_eq!(read(b"a\nb\n").bytes(), b"a\nb\n");
+    }
+
+    #[test]
+    fn format_change_survives_undo() {
+        let mut buffer = read(b"a\n");
+        buffer.set_option("fileformat=dos").unwrap();
+        buffer.begin_group(1);
+        buffer.insert_char(0, 1, 'b', Action::Do);
+        buffer.undo();
+        assert!(buffer.modified);
+
+        buffer.mark_saved();
+        assert!(!buffer.modified);
+    }
+
+    #[test]
+    fn set_line_undoes_after_scrolling() {
+        undo_after_scrolling(|buffer| buffer.set_line(1, vec!['x'], Action::Do), &["0", "1", "2", "3", "4", "x", "6", "7"]);
+    }
+
+    #[test]
+    fn insert_line_undoes_after_scrolling() {
+        undo_after_scrolling(|buffer| buffer.insert_line(1, vec!['x'], Action::Do), &["0", "1", "2", "3", "4", "x", "5", "6", "7"]);
+    }
+
+    #[test]
+    fn delete_line_undoes_after_scrolling() {
+        undo_after_scrolling(|buffer| buffer.delete_line(1, Action::Do), &["0", "1", "2", "3", "4", "6", "7"]);
+    }
+
+    #[test]
+    fn insert_char_undoes_after_scrolling() {
+        undo_after_scrolling(|buffer| buffer.insert_char(1, 1, 'x', Action::Do), &["0", "1", "2", "3", "4", "5x", "6", "7"]);
+    }
+
+    #[test]
+    fn delete_char_undoes_after_scrolling() {
+        undo_after_scrolling(|buffer| buffer.delete_char(1, 0, Action::Do), &["0", "1", "2", "3", "4", "", "6", "7"]);
+    }
+
+    #[test]
+    fn insert_past_line_end_undoes_only_that_char() {
+        let mut buffer = read(b"ab\n");
+        buffer.begin_group(1);
+        buffer.insert_char(0, 10, 'c', Action::Do);
+        assert_eq!(lines(&buffer), ["abc"]);
+
+        buffer.undo();
+        assert_eq!(lines(&buffer), ["ab"]);
+    }
+
+    #[test]
+    fn undoing_back_to_the_save_clears_modified() {
+        let mut buffer = read(b"a\n");
+        buffer.begin_group(1);
+        buffer.insert_char(0, 1, 'b', Action::Do);
+        assert!(buffer.modified);
+
+        buffer.undo();
+        assert!(!buffer.modified);
+        assert!(buffer.is_at_saved_state());
+
+        buffer.redo();
+        assert!(buffer.modified);
+        assert!(!buffer.is_at_saved_state());
+    }
+
+    #[test]
+    fn saved_state_follows_undo_and_redo() {
+        let mut buffer = read(b"a\n");
+        buffer.begin_group(1);
+        buffer.insert_char(0, 1, 'b', Action::Do);
+        buffer.mark_saved();
+        assert!(buffer.is_at_saved_state());
+
+        buffer.begin_group(2);
+        buffer.insert_char(0, 2, 'c', Action::Do);
+        assert!(!buffer.is_at_saved_state());
+        buffer.undo();
+        assert!(buffer.is_at_saved_state());
+        buffer.undo();
+        assert!(!buffer.is_at_saved_state());
+        buffer.redo();
+        assert!(buffer.is_at_saved_state());
+    }
+
+    #[test]
+    fn edit_below_the_save_loses_the_saved_state() {
+        let mut buffer = read(b"a\n");
+        buffer.begin_group(1);
+        buffer.insert_char(0, 1, 'b', Action::Do);
+        buffer.mark_saved();
+        buffer.undo();
+
+        // Branching off from before the save means undo and redo can't get back to it
+        buffer.begin_group(2);
+        buffer.insert_char(0, 1, 'c', Action::Do);
+        buffer.undo();
+        assert!(!buffer.is_at_saved_state());
+        buffer.redo();
+        assert!(!buffer.is_at_saved_state());
+        assert!(buffer.modified);
+    }
+
+    #[test]
+    fn typing_a_word_undoes_in_one_step() {
+        let mut buffer = Buffer::default();
+        buffer.begin_group(1);
+        for (col, c) in "hello".chars().enumerate() {
+            buffer.insert_char(0, col, c, Action::Do);
+        }
+        assert_eq!(lines(&buffer), ["hello"]);
+
+        buffer.undo();
+        assert_eq!(lines(&buffer), [""]);
+    }
+
+    #[test]
+    fn invalid_bytes_open_read_only() {
+        let buffer = read(b"a\xff\0b\nok\n");
+        assert!(buffer.binary);
+        assert!(buffer.readonly);
+        assert_eq!(lines(&buffer), ["a\u{fffd}\0b", "ok"]);
+    }
+
+    #[test]
+    fn valid_utf8_is_not_binary() {
+        let buffer = read("café\n".as_bytes());
+        assert!(!buffer.binary);
+        assert!(!buffer.readonly);
+    }
+
+    #[test]
+    fn undo_takes_back_one_change_per_group() {
+        let mut buffer = Buffer::default();
+        buffer.begin_group(1);
+        for (col, c) in "word".chars().enumerate() {
+            buffer.insert_char(0, col, c, Action::Do);
+        }
+        buffer.begin_group(2);
+        buffer.insert_line(1, vec![], Action::Do);
+        assert_eq!(buffer.history_groups(), 2);
+
+        buffer.undo();
+        assert_eq!(buffer.history_groups(), 1);
+        buffer.undo();
+        assert_eq!(buffer.history_groups(), 0);
+    }
+}
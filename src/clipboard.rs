@@ -0,0 +1,110 @@
+use std::fmt;
+
+// The system clipboard, used by the "+ and "* registers and with clipboard=unnamed. Built
+// without the arboard feature there isn't one, and using it only says so.
+#[derive(Debug)]
+pub struct ClipboardError(String);
+
+impl fmt::Display for ClipboardError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "clipboard unavailable: {}", self.0)
+    }
+}
+
+#[cfg(feature = "arboard")]
+mod system {
+    use std::sync::Mutex;
+
+    use super::ClipboardError;
+
+    // On X11 what was copied only stays on the clipboard while the handle that copied it is
+    // around, so one is kept for as long as the editor runs
+    static CLIPBOARD: Mutex<Option<arboard::Clipboard>> = Mutex::new(None);
+
+    fn with<T>(f: impl FnOnce(&mut arboard::Clipboard) -> Result<T, arboard::Error>) -> Result<T, ClipboardError> {
+        let mut clipboard = CLIPBOARD.lock().unwrap_or_else(|e| e.into_inner());
+        if clipboard.is_none() {
+            *clipboard = Some(arboard::Clipboard::new().map_err(|e| ClipboardError(e.to_string()))?);
+        }
+        f(clipboard.as_mut().unwrap()).map_err(|e| ClipboardError(e.to_string()))
+    }
+
+    pub fn copy(text: String) -> Result<(), ClipboardError> {
+        with(|clipboard| clipboard.set_text(text))
+    }
+
+    pub fn paste() -> Result<String, ClipboardError> {
+        with(|clipboard| clipboard.get_text())
+    }
+}
+
+#[cfg(not(feature = "arboard"))]
+mod system {
+    use super::ClipboardError;
+
+    pub fn copy(_text: String) -> Result<(), ClipboardError> {
+        Err(ClipboardError("built without clipboard support".to_string()))
+    }
+
+    pub fn paste() -> Result<String, ClipboardError> {
+        Err(ClipboardError("built without clipboard support".to_string()))
+    }
+}
+
+// Line-wise text ends with a line ending, so it comes back as lines of its own when put
+pub fn copy(lines: &[Vec<char>], linewise: bool) -> Result<(), ClipboardError> {
+    system::copy(to_text(lines, linewise))
+}
+
+pub fn paste() -> Result<(Vec<Vec<char>>, bool), ClipboardError> {
+    system::paste().map(|text| from_text(&text))
+}
+
+fn to_text(lines: &[Vec<char>], linewise: bool) -> String {
+    let mut text = lines.iter().map(|line| line.iter().collect::<String>()).collect::<Vec<_>>().join("\n");
+    if linewise {
+        text.push('\n');
+    }
+    text
+}
+
+// Text copied elsewhere may have Windows line endings, which don't belong in the lines
+fn from_text(text: &str) -> (Vec<Vec<char>>, bool) {
+    let linewise = text.ends_with('\n');
+    let text = text.strip_suffix('\n').unwrap_or(text);
+    let lines = text.split('\n').map(|line| line.strip_suffix('\r').unwrap_or(line).chars().collect()).collect();
+    (lines, linewise)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(text: &[&str]) -> Vec<Vec<char>> {
+        text.iter().map(|line| line.chars().collect()).collect()
+    }
+
+    #[test]
+    fn text_round_trips() {
+        for (text, linewise) in [(&["a", "b"][..], true), (&["a", "b"][..], false), (&["a"][..], false)] {
+            let copied = to_text(&lines(text), linewise);
+            assert_eq!(from_text(&copied), (lines(text), linewise));
+        }
+    }
+
+    #[test]
+    fn windows_line_endings_are_dropped() {
+        assert_eq!(from_text("a\r\nb\r\n"), (lines(&["a", "b"]), true));
+    }
+
+    // Clipboards can't be reached headless, like on CI, so there it's enough not to panic
+    #[cfg(feature = "arboard")]
+    #[test]
+    fn clipboard_round_trips() {
+        let copied = lines(&["vision", "clipboard"]);
+        if copy(&copied, true).is_err() {
+            return;
+        }
+        assert_eq!(paste().unwrap(), (copied, true));
+    }
+}
@@ -1,65 +1,274 @@
-use std::{fs, io::stdout, process::exit, str::FromStr};
+use std::{cmp::{self, Ordering}, io::stdout, path::Path, process::exit, str::FromStr};
 
-use crossterm::{cursor::MoveTo, execute, terminal::{disable_raw_mode, Clear, ClearType}};
+use crossterm::{cursor::MoveTo, event::DisableMouseCapture, execute, terminal::{disable_raw_mode, Clear, ClearType}};
 
-use crate::Buffer;
+use crate::{utils, Action, Address, Buffer, Editor, Range, Session};
 
 pub trait Run {
-    fn run(&self, buffer: &mut Buffer) -> Result<(), RunError>;
+    fn run(&self, editor: &mut Editor) -> Result<(), RunError>;
 }
 
 #[derive(Debug, Clone, Copy)]
 pub enum CommandError {
     UnknownCommand,
+    InvalidArgument,
+    RangeNotAllowed,
 }
 
 #[derive(Debug, Clone, Copy)]
 pub enum RunError {
     UnknownPath,
-    QuitOnModified
+    QuitOnModified,
+    UnknownOption,
+    InvalidValue,
+    InvalidRange,
+    PartialWrite,
+    WriteFailed,
+    FileExists,
+    NoPattern,
+    PatternNotFound,
+    ReadOnly,
+    IsDirectory,
+    AlreadySplit,
+    LastWindow,
+    MarkNotSet,
+    NoAlternateBuffer,
+    UnknownBuffer,
+    NoHelp,
 }
 
 pub enum Command {
     Quit(QuitCommand),
     Save(SaveCommand),
-    SaveQuit(SaveQuitCommand)
+    SaveQuit(SaveQuitCommand),
+    New(NewCommand),
+    Set(SetCommand),
+    MakeSession(MakeSessionCommand),
+    Edit(EditCommand),
+    Goto(GotoCommand),
+    Substitute(SubstituteCommand),
+    Read(ReadCommand),
+    Sort(SortCommand),
+    Uniq(UniqCommand),
+    Delete(DeleteCommand),
+    NoHighlight(NoHighlightCommand),
+    SwitchBuffer(SwitchBufferCommand),
+    ListBuffers(ListBuffersCommand),
+    CloseBuffer(CloseBufferCommand),
+    Split(SplitCommand),
+    Help(HelpCommand),
 }
 
 pub struct QuitCommand {
     discard: bool,
 }
-pub struct SaveCommand;
+
+#[derive(Default)]
+pub struct SaveCommand {
+    range: Option<Range>,
+    path: Option<String>,
+    append: bool,
+    force: bool,
+}
 
 pub struct SaveQuitCommand;
 
+pub struct NewCommand;
+
+pub struct SetCommand {
+    option: String,
+}
+
+pub struct EditCommand {
+    path: String,
+}
+
+pub struct GotoCommand {
+    line: Address,
+}
+
+pub struct SubstituteCommand {
+    range: Option<Range>,
+    pattern: Vec<char>,
+    replacement: Vec<char>,
+    global: bool,
+}
+
+pub struct ReadCommand {
+    path: String,
+}
+
+pub struct SortCommand {
+    range: Option<Range>,
+    reverse: bool,
+    numeric: bool,
+    unique: bool,
+}
+
+pub struct UniqCommand {
+    range: Option<Range>,
+}
+
+pub struct DeleteCommand {
+    range: Option<Range>,
+}
+
+pub struct NoHighlightCommand;
+
+pub struct SwitchBufferCommand {
+    forward: bool,
+}
+
+pub struct ListBuffersCommand;
+
+pub struct CloseBufferCommand {
+    discard: bool,
+}
+
+pub struct SplitCommand;
+
+pub struct HelpCommand {
+    topic: Option<String>,
+}
+
+// Sections of the help start with a line ending in their topic, like "*windows*"
+const HELP: &str = include_str!("help.txt");
+
+pub struct MakeSessionCommand {
+    path: String,
+    overwrite: bool,
+}
+
 impl FromStr for Command {
     type Err = CommandError;
     fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (range, s) = Range::parse(s);
+
+        // Patterns can hold spaces, so this has to come before splitting off the argument
+        if let Some(substitute) = s.strip_prefix("s/") {
+            return Ok(Self::Substitute(SubstituteCommand::parse(range, substitute)?));
+        }
+        let (name, arg) = match s.split_once(' ') {
+            Some((name, arg)) => (name, Some(arg.trim())),
+            None => (s, None),
+        };
+
+        if name == "w" || name == "w!" {
+            return Ok(Self::Save(SaveCommand::parse(range, arg, name == "w!")?));
+        }
+
+        if name == "sort" || name == "sort!" {
+            // Flags can come in any order, like "nu" or "un"
+            let flags = arg.unwrap_or("");
+            if !flags.chars().all(|c| c == 'n' || c == 'u') {
+                return Err(CommandError::InvalidArgument);
+            }
+
+            let (numeric, unique) = (flags.contains('n'), flags.contains('u'));
+            return Ok(Self::Sort(SortCommand { range, reverse: name == "sort!", numeric, unique }));
+        }
+
+        if name == "uniq" {
+            return Ok(Self::Uniq(UniqCommand { range }));
+        }
+
+        if name == "d" || name == "delete" {
+            return Ok(Self::Delete(DeleteCommand { range }));
+        }
+
+        // A range on its own, like ":42" or ":$", jumps to the line it ends on
+        if let Some(range) = range
+            && s.is_empty()
+        {
+            return Ok(Self::Goto(GotoCommand { line: range.end }));
+        }
+
+        if range.is_some() {
+            return Err(CommandError::RangeNotAllowed);
+        }
+
+        if let ("set", Some(option)) = (name, arg) {
+            return Ok(Self::Set(SetCommand { option: option.to_string() }));
+        }
+
+        if let Some(overwrite) = name.strip_prefix("mksession") {
+            if !overwrite.is_empty() && overwrite != "!" {
+                return Err(CommandError::UnknownCommand);
+            }
+
+            let path = arg.unwrap_or("Session.vision").to_string();
+            return Ok(Self::MakeSession(MakeSessionCommand { path, overwrite: overwrite == "!" }));
+        }
+
+        // The buffer being left stays open, so there's nothing for ! to throw away
+        if let ("e" | "e!", Some(path)) = (name, arg) {
+            return Ok(Self::Edit(EditCommand { path: path.to_string() }));
+        }
+
+        if let ("r", Some(path)) = (name, arg) {
+            return Ok(Self::Read(ReadCommand { path: path.to_string() }));
+        }
+
+        if name == "h" || name == "help" {
+            return Ok(Self::Help(HelpCommand { topic: arg.filter(|topic| !topic.is_empty()).map(str::to_string) }));
+        }
+
         match s {
             "q" => Ok(Self::Quit(QuitCommand { discard: false })),
             "q!" => Ok(Self::Quit(QuitCommand { discard: true })),
-            "w" => Ok(Self::Save(SaveCommand)),
             "wq" => Ok(Self::SaveQuit(SaveQuitCommand)),
+            "noh" | "nohlsearch" => Ok(Self::NoHighlight(NoHighlightCommand)),
+            "bn" | "bnext" => Ok(Self::SwitchBuffer(SwitchBufferCommand { forward: true })),
+            "bp" | "bprevious" | "bN" | "bNext" => Ok(Self::SwitchBuffer(SwitchBufferCommand { forward: false })),
+            "ls" | "buffers" => Ok(Self::ListBuffers(ListBuffersCommand)),
+            "bd" | "bdelete" => Ok(Self::CloseBuffer(CloseBufferCommand { discard: false })),
+            "bd!" | "bdelete!" => Ok(Self::CloseBuffer(CloseBufferCommand { discard: true })),
+            "sp" | "split" => Ok(Self::Split(SplitCommand)),
+            // Like :e, the buffer being left stays open, so ! has nothing to throw away
+            "new" | "enew" | "new!" | "enew!" => Ok(Self::New(NewCommand)),
             _ => Err(CommandError::UnknownCommand),
         }
     }
 }
 
 impl Command {
-    pub fn run(&self, buffer: &mut Buffer) -> Result<(), RunError> {
+    pub fn run(&self, editor: &mut Editor) -> Result<(), RunError> {
         match self {
-            Self::Quit(quit) => quit.run(buffer),
-            Self::Save(save) => save.run(buffer),
-            Self::SaveQuit(save_quit) => save_quit.run(buffer),
+            Self::Quit(quit) => quit.run(editor),
+            Self::Save(save) => save.run(editor),
+            Self::SaveQuit(save_quit) => save_quit.run(editor),
+            Self::New(new) => new.run(editor),
+            Self::Set(set) => set.run(editor),
+            Self::MakeSession(make_session) => make_session.run(editor),
+            Self::Edit(edit) => edit.run(editor),
+            Self::Goto(goto) => goto.run(editor),
+            Self::Substitute(substitute) => substitute.run(editor),
+            Self::Read(read) => read.run(editor),
+            Self::Sort(sort) => sort.run(editor),
+            Self::Uniq(uniq) => uniq.run(editor),
+            Self::Delete(delete) => delete.run(editor),
+            Self::NoHighlight(no_highlight) => no_highlight.run(editor),
+            Self::SwitchBuffer(switch_buffer) => switch_buffer.run(editor),
+            Self::ListBuffers(list_buffers) => list_buffers.run(editor),
+            Self::CloseBuffer(close_buffer) => close_buffer.run(editor),
+            Self::Split(split) => split.run(editor),
+            Self::Help(help) => help.run(editor),
         }
     }
 }
 
 
 impl Run for QuitCommand {
-    fn run(&self, buffer: &mut Buffer) -> Result<(), RunError> {
-        if self.discard || !buffer.modified {
-            execute!(stdout(), Clear(ClearType::All), MoveTo(0, 0)).unwrap();
+    // With the screen split only the window is closed, its buffer staying open. Otherwise quitting
+    // closes every buffer, so none of them can have unsaved changes.
+    fn run(&self, editor: &mut Editor) -> Result<(), RunError> {
+        if editor.close_window() {
+            return Ok(());
+        }
+
+        if self.discard || !editor.buffers().any(|buffer| !buffer.scratch && buffer.modified) {
+            // Left capturing the mouse, the terminal would stop scrolling and selecting on its own
+            execute!(stdout(), Clear(ClearType::All), MoveTo(0, 0), DisableMouseCapture).unwrap();
             print!("\x1b[3J");
             disable_raw_mode().unwrap();
             exit(0);
@@ -69,15 +278,92 @@ impl Run for QuitCommand {
     }
 }
 
+impl SaveCommand {
+    // Parses the argument of ":w", which is either a file or ">> file" to append instead of overwrite
+    fn parse(range: Option<Range>, arg: Option<&str>, force: bool) -> Result<Self, CommandError> {
+        let mut save = Self { range, force, ..Default::default() };
+
+        if let Some(arg) = arg {
+            let target = match arg.strip_prefix(">>") {
+                Some(target) => {
+                    save.append = true;
+                    target.trim()
+                }
+                None => arg,
+            };
+
+            if !target.is_empty() {
+                save.path = Some(target.to_string());
+            }
+        }
+
+        Ok(save)
+    }
+
+    fn write_lines(&self, editor: &Editor) -> Result<(), RunError> {
+        let path = self.path.as_ref().or(editor.file.path.as_ref()).ok_or(RunError::UnknownPath)?;
+
+        // Overwriting the buffer's own file with only some of its lines would lose the rest
+        if !self.append && self.path.is_none() {
+            return Err(RunError::PartialWrite)
+        }
+
+        let (start, end) = self.range.unwrap_or(Range::whole()).resolve(editor)?;
+        editor.file.write_lines(path, start..=end, self.append).map_err(|_| RunError::WriteFailed)
+    }
+}
+
 impl Run for SaveCommand {
-    fn run(&self, buffer: &mut Buffer) -> Result<(), RunError> {
+    fn run(&self, editor: &mut Editor) -> Result<(), RunError> {
+        if let Some(path) = self.path.as_ref().or(editor.file.path.as_ref())
+            && Path::new(path).is_dir()
+        {
+            return Err(RunError::IsDirectory);
+        }
+
+        // Like vim's E13, writing over some other file that's already there needs a !
+        if let Some(path) = &self.path
+            && !self.append
+            && !self.force
+            && editor.file.path.as_ref() != Some(path)
+            && Path::new(path).exists()
+        {
+            return Err(RunError::FileExists);
+        }
+
+        // Saving an unnamed buffer to a file gives it that name from then on
+        if let Some(path) = &self.path
+            && editor.file.path.is_none()
+            && !self.append
+            && self.range.is_none()
+        {
+            let buffer = &mut editor.file;
+            buffer.path = Some(path.clone());
+            buffer.scratch = false;
+            buffer.write().map_err(|_| RunError::WriteFailed)?;
+            buffer.mark_saved();
+            return Ok(());
+        }
+
+        // Writing elsewhere leaves the buffer's own path and modified flag alone. Naming the
+        // buffer's own file is just a save.
+        let elsewhere = self.path.is_some() && self.path != editor.file.path;
+        if self.append || self.range.is_some() || elsewhere {
+            return self.write_lines(editor);
+        }
+
+        let buffer = &mut editor.file;
         if buffer.path.is_none() {
             return Err(RunError::UnknownPath)
         }
+        if buffer.readonly && !self.force {
+            return Err(RunError::ReadOnly)
+        }
 
         if buffer.modified {
-            buffer.write().expect("Failed to write to disk while saving file");
-            buffer.modified = false;
+            // Forcing past 'readonly' can still hit a file the OS won't let us write
+            buffer.write().map_err(|_| RunError::WriteFailed)?;
+            buffer.mark_saved();
         }
 
         Ok(())
@@ -85,8 +371,451 @@ impl Run for SaveCommand {
 }
 
 impl Run for SaveQuitCommand {
-    fn run(&self, buffer: &mut Buffer) -> Result<(), RunError> {
-        SaveCommand.run(buffer)?;
-        Ok(QuitCommand {discard: false}.run(buffer)?)
+    fn run(&self, editor: &mut Editor) -> Result<(), RunError> {
+        SaveCommand::default().run(editor)?;
+        QuitCommand {discard: false}.run(editor)
+    }
+}
+
+impl Run for NewCommand {
+    fn run(&self, editor: &mut Editor) -> Result<(), RunError> {
+        editor.add_buffer(Buffer::scratch());
+        Ok(())
+    }
+}
+
+impl Run for EditCommand {
+    fn run(&self, editor: &mut Editor) -> Result<(), RunError> {
+        // A file that's already open is switched to rather than opened twice
+        let open = editor.buffers().position(|buffer| buffer.path.as_deref() == Some(self.path.as_str()));
+        if let Some(index) = open {
+            editor.switch_buffer(index);
+            return Ok(());
+        }
+
+        if Path::new(&self.path).is_dir() {
+            return Err(RunError::IsDirectory)
+        }
+
+        // Like opening from the command line, a file that doesn't exist yet starts out empty
+        // Why it couldn't be opened is more use than that it couldn't, so the reason is shown
+        let buffer = match Path::new(&self.path).is_file() {
+            true => match self.path.parse::<Buffer>() {
+                Ok(buffer) => buffer,
+                Err(e) => {
+                    editor.message = Some(format!("cannot open {}: {}", self.path, e));
+                    return Ok(());
+                }
+            },
+            false => Buffer::new(Some(self.path.clone())),
+        };
+
+        editor.add_buffer(buffer);
+        Ok(())
     }
-}
\ No newline at end of file
+}
+
+impl Run for SwitchBufferCommand {
+    // Wraps around from the last buffer to the first, and the other way going back
+    fn run(&self, editor: &mut Editor) -> Result<(), RunError> {
+        let count = editor.buffer_count();
+        let index = match self.forward {
+            true => (editor.buffer_index() + 1) % count,
+            false => (editor.buffer_index() + count - 1) % count,
+        };
+        editor.switch_buffer(index);
+        Ok(())
+    }
+}
+
+impl Run for ListBuffersCommand {
+    // Each buffer's number and name, with % marking the current one and + unsaved changes
+    fn run(&self, editor: &mut Editor) -> Result<(), RunError> {
+        let current = editor.buffer_index();
+        let list: Vec<String> = editor
+            .buffers()
+            .enumerate()
+            .map(|(i, buffer)| {
+                let marker = if i == current { "%" } else { " " };
+                let modified = if buffer.modified { " +" } else { "" };
+                format!("{}{}\"{}\"{}", i + 1, marker, buffer.name(), modified)
+            })
+            .collect();
+
+        editor.message = Some(list.join("  "));
+        Ok(())
+    }
+}
+
+impl Run for CloseBufferCommand {
+    fn run(&self, editor: &mut Editor) -> Result<(), RunError> {
+        let buffer = &editor.file;
+        if !self.discard && !buffer.scratch && buffer.modified {
+            return Err(RunError::QuitOnModified)
+        }
+
+        // Closing the only buffer there is leaves nothing to edit, so it quits instead
+        if !editor.close_buffer() {
+            return QuitCommand { discard: self.discard }.run(editor);
+        }
+        Ok(())
+    }
+}
+
+impl Run for SplitCommand {
+    fn run(&self, editor: &mut Editor) -> Result<(), RunError> {
+        match editor.split_window() {
+            true => Ok(()),
+            false => Err(RunError::AlreadySplit),
+        }
+    }
+}
+
+impl Run for HelpCommand {
+    // Opens the help in a window of its own, with the topic at the top, so :q closes it again.
+    // Help that's already open is gone back to instead.
+    fn run(&self, editor: &mut Editor) -> Result<(), RunError> {
+        let tag = format!("*{}*", self.topic.as_deref().unwrap_or("contents"));
+        let row = HELP.lines().position(|line| line.trim_end().ends_with(&tag)).ok_or(RunError::NoHelp)?;
+
+        let open = editor.buffers().position(|buffer| buffer.help);
+        match open {
+            Some(index) => editor.switch_buffer(index),
+            None => {
+                editor.split_window();
+                editor.add_buffer(Buffer::help(HELP));
+            }
+        }
+
+        editor.file.start = row;
+        editor.jump_to(row, 0);
+        editor.redraw();
+        Ok(())
+    }
+}
+
+impl Run for GotoCommand {
+    fn run(&self, editor: &mut Editor) -> Result<(), RunError> {
+        let row = self.line.resolve_clamped(editor);
+        editor.jump_to(row, 0);
+        Ok(())
+    }
+}
+
+impl SubstituteCommand {
+    // Parses "pattern/replacement/flags" from ":s/pattern/replacement/flags"
+    fn parse(range: Option<Range>, s: &str) -> Result<Self, CommandError> {
+        let mut parts = s.splitn(3, '/');
+        let pattern = parts.next().unwrap_or_default();
+        let replacement = parts.next().unwrap_or_default();
+
+        let global = match parts.next().unwrap_or_default() {
+            "" => false,
+            "g" => true,
+            _ => return Err(CommandError::InvalidArgument),
+        };
+
+        if pattern.is_empty() {
+            return Err(CommandError::InvalidArgument);
+        }
+
+        Ok(Self {
+            range,
+            pattern: pattern.chars().collect(),
+            replacement: replacement.chars().collect(),
+            global,
+        })
+    }
+
+    // Returns the line with the pattern replaced and how many replacements were made
+    fn substitute(&self, line: &[char], ignore_case: bool) -> (Vec<char>, usize) {
+        let mut new_line = Vec::with_capacity(line.len());
+        let mut count = 0;
+        let mut col = 0;
+
+        while let Some(found) = utils::find_chars(line, &self.pattern, col, ignore_case) {
+            new_line.extend_from_slice(&line[col..found]);
+            new_line.extend_from_slice(&self.replacement);
+            col = found + self.pattern.len();
+            count += 1;
+
+            if !self.global {
+                break;
+            }
+        }
+
+        new_line.extend_from_slice(&line[col..]);
+        (new_line, count)
+    }
+}
+
+impl Run for SubstituteCommand {
+    fn run(&self, editor: &mut Editor) -> Result<(), RunError> {
+        if !editor.check_modifiable() {
+            return Ok(());
+        }
+
+        let (start, end) = self.range.unwrap_or(Range::current()).resolve(editor)?;
+
+        let ignore_case = editor.settings.ignore_case(&self.pattern);
+        let mut count = 0;
+        let mut lines = 0;
+        let mut last_row = None;
+        for row in start..=end {
+            let Some(line) = editor.file.get_line_at(row) else {
+                break;
+            };
+
+            let (new_line, replaced) = self.substitute(line, ignore_case);
+            if replaced > 0 {
+                editor.file.set_line_at(row, new_line, Action::Do);
+                count += replaced;
+                lines += 1;
+                last_row = Some(row);
+            }
+        }
+
+        let row = last_row.ok_or(RunError::PatternNotFound)?;
+
+        // Like vim, leave the cursor on the last line that changed
+        let col = editor.file.get_line_at(row).map_or(0, |line| utils::first_non_blank(line));
+        editor.jump_to(row, col);
+
+        let plural = if count == 1 { "substitution" } else { "substitutions" };
+        editor.report_lines(lines, format!("{} {} on {} lines", count, plural, lines));
+        editor.render();
+        Ok(())
+    }
+}
+
+impl Run for DeleteCommand {
+    // Deleted lines go into the register, like dd
+    fn run(&self, editor: &mut Editor) -> Result<(), RunError> {
+        if !editor.check_modifiable() {
+            return Ok(());
+        }
+
+        let (start, end) = self.range.unwrap_or(Range::current()).resolve(editor)?;
+        let lines: Vec<Vec<char>> = (start..=end).filter_map(|row| editor.file.get_line_at(row).cloned()).collect();
+        let count = lines.len();
+        editor.yank(lines, true);
+
+        for _ in 0..count {
+            // A buffer always keeps one line, so the last one left is emptied instead
+            if editor.file.length() == 1 {
+                editor.file.set_line_at(start, vec![], Action::Do);
+            } else {
+                editor.file.delete_line_at(start, Action::Do);
+            }
+        }
+
+        let row = cmp::min(start, editor.file.length() - 1);
+        let col = editor.file.get_line_at(row).map_or(0, |line| utils::first_non_blank(line));
+        editor.jump_to(row, col);
+
+        editor.report_lines(count, format!("{} fewer lines", count));
+        editor.render();
+        Ok(())
+    }
+}
+
+impl Run for ReadCommand {
+    fn run(&self, editor: &mut Editor) -> Result<(), RunError> {
+        if !editor.check_modifiable() {
+            return Ok(());
+        }
+
+        // Read the way :e opens files, so line endings and invalid bytes are dealt with the same
+        let file = match self.path.parse::<Buffer>() {
+            Ok(file) => file,
+            Err(e) => {
+                editor.message = Some(format!("cannot open {}: {}", self.path, e));
+                return Ok(());
+            }
+        };
+        if file.is_empty() {
+            return Ok(());
+        }
+        let lines: Vec<Vec<char>> = (0..file.length()).filter_map(|row| file.get_line_at(row).cloned()).collect();
+
+        // The file goes in below the cursor line, and the cursor moves down onto its first line
+        let row = editor.cursor_at().0 + 1;
+        let col = utils::first_non_blank(&lines[0]);
+        for (i, line) in lines.into_iter().enumerate() {
+            editor.file.insert_line_at(row + i, line, Action::Do);
+        }
+
+        editor.jump_to(row, col);
+        editor.render();
+        Ok(())
+    }
+}
+
+impl SortCommand {
+    // The first number on the line, which numeric sorting goes by. Lines without one go first.
+    fn number(line: &[char]) -> Option<i64> {
+        let start = line.iter().position(|c| c.is_ascii_digit())?;
+        let end = line[start..].iter().position(|c| !c.is_ascii_digit()).map_or(line.len(), |len| start + len);
+
+        let negative = start > 0 && line[start - 1] == '-';
+        let number = line[start..end].iter().collect::<String>().parse::<i64>().unwrap_or(i64::MAX);
+        Some(if negative { -number } else { number })
+    }
+}
+
+impl Run for SortCommand {
+    fn run(&self, editor: &mut Editor) -> Result<(), RunError> {
+        if !editor.check_modifiable() {
+            return Ok(());
+        }
+
+        let (start, end) = self.range.unwrap_or(Range::whole()).resolve(editor)?;
+        let lines: Vec<Vec<char>> = (start..=end).filter_map(|row| editor.file.get_line_at(row).cloned()).collect();
+
+        let compare = |a: &Vec<char>, b: &Vec<char>| match self.numeric {
+            true => Self::number(a).cmp(&Self::number(b)),
+            false => a.cmp(b),
+        };
+
+        // Stable, so lines that compare equal keep their order either way round
+        let mut sorted = lines.clone();
+        sorted.sort_by(|a, b| {
+            let order = compare(a, b);
+            if self.reverse { order.reverse() } else { order }
+        });
+
+        // Sorted, any duplicates sit next to each other. With n, lines with the same number count
+        // as the same, like vim.
+        if self.unique {
+            sorted.dedup_by(|a, b| compare(a, b) == Ordering::Equal);
+        }
+
+        replace_lines(editor, start, &lines, sorted);
+        Ok(())
+    }
+}
+
+impl Run for UniqCommand {
+    // Like :sort u without the sorting, so only duplicates right after each other go
+    fn run(&self, editor: &mut Editor) -> Result<(), RunError> {
+        if !editor.check_modifiable() {
+            return Ok(());
+        }
+
+        let (start, end) = self.range.unwrap_or(Range::whole()).resolve(editor)?;
+        let lines: Vec<Vec<char>> = (start..=end).filter_map(|row| editor.file.get_line_at(row).cloned()).collect();
+
+        let mut unique = lines.clone();
+        unique.dedup();
+
+        replace_lines(editor, start, &lines, unique);
+        Ok(())
+    }
+}
+
+// Puts `new` in place of the lines from `start` that were `old`, which it may be shorter than, and
+// reports how many went or, past the report option, how many changed. Only lines that changed
+// are edits, all undone together.
+fn replace_lines(editor: &mut Editor, start: usize, old: &[Vec<char>], new: Vec<Vec<char>>) {
+    let removed = old.len() - new.len();
+    let mut changed = 0;
+    for (i, line) in new.into_iter().enumerate() {
+        if old[i] != line {
+            editor.file.set_line_at(start + i, line, Action::Do);
+            changed += 1;
+        }
+    }
+    for _ in 0..removed {
+        editor.file.delete_line_at(start + old.len() - removed, Action::Do);
+    }
+
+    let plural = if removed == 1 { "line" } else { "lines" };
+    match removed {
+        0 => editor.report_lines(changed, format!("{} lines changed", changed)),
+        _ => editor.message = Some(format!("{} duplicate {} removed", removed, plural)),
+    }
+
+    let col = editor.file.get_line_at(start).map_or(0, |line| utils::first_non_blank(line));
+    editor.jump_to(start, col);
+    editor.render();
+}
+
+impl Run for NoHighlightCommand {
+    // The pattern stays, so n and N still search for it and turn the highlighting back on
+    fn run(&self, editor: &mut Editor) -> Result<(), RunError> {
+        editor.highlight_search = false;
+        editor.render();
+        Ok(())
+    }
+}
+
+impl Run for SetCommand {
+    fn run(&self, editor: &mut Editor) -> Result<(), RunError> {
+        match editor.file.set_option(&self.option) {
+            Err(RunError::UnknownOption) => editor.settings.set(&self.option)?,
+            result => result?,
+        }
+        editor.apply_settings();
+
+        editor.redraw();
+        Ok(())
+    }
+}
+
+impl Run for MakeSessionCommand {
+    fn run(&self, editor: &mut Editor) -> Result<(), RunError> {
+        if !self.overwrite && Path::new(&self.path).exists() {
+            return Err(RunError::FileExists)
+        }
+
+        Session::capture(editor).write(&self.path).map_err(|_| RunError::WriteFailed)
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn substitute(command: &str, line: &str, ignore_case: bool) -> (String, usize) {
+        let command = SubstituteCommand::parse(None, command).unwrap();
+        let (line, count) = command.substitute(&line.chars().collect::<Vec<char>>(), ignore_case);
+        (line.into_iter().collect(), count)
+    }
+
+    #[test]
+    fn substitutes_the_first_match() {
+        assert_eq!(substitute("foo/bar/", "foo foo", false), ("bar foo".to_string(), 1));
+    }
+
+    #[test]
+    fn substitutes_every_match_with_g() {
+        assert_eq!(substitute("o/0/g", "foo boo", false), ("f00 b00".to_string(), 4));
+        assert_eq!(substitute("aa/a/g", "aaaa", false), ("aa".to_string(), 2));
+    }
+
+    #[test]
+    fn substitutes_nothing_without_a_match() {
+        assert_eq!(substitute("x/y/g", "foo", false), ("foo".to_string(), 0));
+        assert_eq!(substitute("FOO/bar/", "foo", false), ("foo".to_string(), 0));
+        assert_eq!(substitute("FOO/bar/", "foo", true), ("bar".to_string(), 1));
+    }
+
+    #[test]
+    fn replacement_can_be_empty() {
+        assert_eq!(substitute("o//g", "foo", false), ("f".to_string(), 2));
+    }
+
+    #[test]
+    fn parse_rejects_bad_patterns_and_flags() {
+        assert!(matches!(SubstituteCommand::parse(None, "/bar/"), Err(CommandError::InvalidArgument)));
+        assert!(matches!(SubstituteCommand::parse(None, "foo/bar/x"), Err(CommandError::InvalidArgument)));
+    }
+
+    #[test]
+    fn parses_a_ranged_substitution() {
+        let Ok(Command::Substitute(command)) = "%s/a/b/g".parse::<Command>() else {
+            panic!("not a substitution");
+        };
+        assert_eq!(command.range, Some(Range::whole()));
+        assert!(command.global);
+    }
+}
@@ -0,0 +1,79 @@
+use std::path::Path;
+
+// Only Markdown has conceal rules so far
+pub fn has_conceal_rules(path: &str) -> bool {
+    matches!(
+        Path::new(path).extension().and_then(|ext| ext.to_str()),
+        Some("md" | "markdown")
+    )
+}
+
+// Hides the markers around Markdown emphasis like *this*, **this** and _this_. With level 1 each
+// hidden marker is replaced by a single space, higher levels hide it entirely.
+pub fn conceal_line(line: &[char], level: u8) -> Vec<char> {
+    let mut hidden = vec![false; line.len()];
+
+    let mut i = 0;
+    while i < line.len() {
+        let marker = line[i];
+        if marker != '*' && marker != '_' {
+            i += 1;
+            continue;
+        }
+
+        let run = marker_run(line, i, marker);
+        let text_start = i + run;
+
+        // Emphasis can't open on whitespace and is at most three markers deep (***bold italic***).
+        // Underscores inside a word like snake_case never start emphasis.
+        if run > 3
+            || line.get(text_start).is_none_or(|c| c.is_whitespace())
+            || (marker == '_' && i > 0 && line[i - 1].is_alphanumeric())
+        {
+            i = text_start;
+            continue;
+        }
+
+        match closing_run(line, text_start, marker, run) {
+            Some(close) => {
+                hidden[i..text_start].fill(true);
+                hidden[close..close + run].fill(true);
+                i = close + run;
+            }
+            None => i = text_start,
+        }
+    }
+
+    let mut concealed = Vec::with_capacity(line.len());
+    for (i, c) in line.iter().enumerate() {
+        if !hidden[i] {
+            concealed.push(*c);
+        } else if level == 1 && (i == 0 || !hidden[i - 1]) {
+            concealed.push(' ');
+        }
+    }
+    concealed
+}
+
+fn marker_run(line: &[char], start: usize, marker: char) -> usize {
+    line[start..].iter().take_while(|c| **c == marker).count()
+}
+
+// Finds a run of exactly `run` markers that closes emphasis opened just before `start`
+fn closing_run(line: &[char], start: usize, marker: char, run: usize) -> Option<usize> {
+    let mut i = start + 1;
+    while i < line.len() {
+        if line[i] != marker {
+            i += 1;
+            continue;
+        }
+
+        let len = marker_run(line, i, marker);
+        let intraword = marker == '_' && line.get(i + len).is_some_and(|c| c.is_alphanumeric());
+        if len == run && !line[i - 1].is_whitespace() && !intraword {
+            return Some(i);
+        }
+        i += len;
+    }
+    None
+}
@@ -1,43 +1,67 @@
-use std::io::stdout;
+use std::{io::stdout, sync::atomic::{AtomicU16, Ordering}};
 
 use crossterm::{cursor::{position, MoveTo}, execute};
 
-use crate::{Action, History, Redo, Undo};
+use crate::{utils, Action, History, Redo, Undo};
 
+// Columns taken up by the line number gutter, set on every render
+static GUTTER: AtomicU16 = AtomicU16::new(0);
+
+// Row from the top of the file and char index, as the editor's cursor_at gives them. Like
+// buffer edits, they don't depend on how far the view was scrolled when they were recorded.
 #[derive(Clone, Copy)]
 pub struct CursorPosition {
-    old: (u16, u16),
-    new: (u16, u16),
+    old: (usize, usize),
+    new: (usize, usize),
 }
 
 impl CursorPosition {
-    pub fn new(old: (u16, u16), new: (u16, u16)) -> Self {
+    pub fn new(old: (usize, usize), new: (usize, usize)) -> Self {
         Self {old, new}
     }
 }
 
 pub struct Cursor {
-    pub history: History<CursorPosition>
+    pub history: History<CursorPosition>,
+
+    // Where the last undo or redo put the cursor back to, left for the editor to place
+    pub restored: Option<(usize, usize)>,
 }
 
 impl Cursor {
     pub fn new(history: History<CursorPosition>) -> Self {
-        Self { history }
+        Self { history, restored: None }
     }
 
+    // Positions are in text columns and rows of the current window, so the gutter to the left of
+    // the text and any window above are left out
     pub fn pos() -> (u16, u16) {
-        position().unwrap()
+        let (col, row) = position().unwrap();
+        (col.saturating_sub(Self::gutter()), row.saturating_sub(utils::window_top()))
     }
 
     pub fn move_to(pos: (u16, u16)) {
-        execute!(stdout(), MoveTo(pos.0, pos.1)).unwrap();
+        execute!(stdout(), MoveTo(pos.0 + Self::gutter(), pos.1 + utils::window_top())).unwrap();
+    }
+
+    pub fn gutter() -> u16 {
+        GUTTER.load(Ordering::Relaxed)
+    }
+
+    pub fn set_gutter(width: u16) {
+        GUTTER.store(width, Ordering::Relaxed);
     }
 }
 
 impl Undo for Cursor {
+    // Walks back through the whole group, ending up where the cursor was before it
     fn undo(&mut self) {
-        if let Some(prev_position) = self.history.last_from(Action::Undo) {
-            Cursor::move_to(prev_position.old);
+        let group = self.history.last_group(Action::Undo);
+
+        while let Some(prev_position) = self.history.last_from(Action::Undo)
+            && self.history.last_group(Action::Undo) == group
+        {
+            self.restored = Some(prev_position.old);
             self.history.update(prev_position, Action::Undo);
         }
     }
@@ -45,9 +69,13 @@ impl Undo for Cursor {
 
 impl Redo for Cursor {
     fn redo(&mut self) {
-        if let Some(next_position) = self.history.last_from(Action::Redo) {
-            Cursor::move_to(next_position.new);
+        let group = self.history.last_group(Action::Redo);
+
+        while let Some(next_position) = self.history.last_from(Action::Redo)
+            && self.history.last_group(Action::Redo) == group
+        {
+            self.restored = Some(next_position.new);
             self.history.update(next_position, Action::Redo);
         }
     }
-}
\ No newline at end of file
+}
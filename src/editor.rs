@@ -1,19 +1,17 @@
-use std::{
-    cmp::{self, max}, collections::VecDeque, io::{stdout, Write}, path::{self, Path}, process::exit
-};
+use std::{cmp, io::stdout, time::Duration};
 
 use crossterm::{
     cursor::{
-        position, Hide, MoveDown, MoveLeft, MoveRight, MoveTo, MoveToNextLine, MoveToPreviousLine, MoveUp, RestorePosition, SavePosition, SetCursorStyle, Show
+        position, Hide, MoveTo, SetCursorStyle, Show
     },
-    event::{read, Event, KeyCode, KeyEvent},
+    event::{poll, read, EnableMouseCapture, Event},
     execute,
-    style::Print,
-    terminal::{disable_raw_mode, enable_raw_mode, size, window_size, Clear, ClearType, SetSize},
+    terminal::{disable_raw_mode, enable_raw_mode, size, Clear, ClearType},
 };
+
 use crossterm::style::Color;
 
-use crate::{mode::*, print_bg, print_fg, utils, Buffer, Command, Cursor, CursorPosition, History, Redo, Undo};
+use crate::{clipboard, mode::*, print_bg, print_fg, utils, Action, Buffer, Cursor, CursorPosition, History, LineEnding, Overlay, Redo, Region, RunError, Selection, SelectionKind, Settings, Undo};
 use cmp::min;
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -25,12 +23,13 @@ pub enum Direction {
 }
 
 impl Direction {
+    // Stops at the top and left edges rather than wrapping around
     pub fn add(&self, pos: (u16, u16)) -> (u16, u16) {
         match self {
-            Direction::Up => (pos.0, pos.1 - 1),
-            Direction::Down => (pos.0, pos.1 + 1),
-            Direction::Left => (pos.0 - 1, pos.1),
-            Direction::Right => (pos.0 + 1, pos.1),
+            Direction::Up => (pos.0, pos.1.saturating_sub(1)),
+            Direction::Down => (pos.0, pos.1.saturating_add(1)),
+            Direction::Left => (pos.0.saturating_sub(1), pos.1),
+            Direction::Right => (pos.0.saturating_add(1), pos.1),
         }
     }
 }
@@ -39,21 +38,133 @@ pub struct Editor {
     pub file: Buffer,
     pub cursor: Cursor,
     pub mode: Mode,
+    pub settings: Settings,
+
+    // Shown next to the mode indicator until the next key press
+    pub message: Option<String>,
+
+    // Last pattern searched for with /, repeated by n and N
+    pub search: Option<String>,
+
+    // Whether matches of the last search are highlighted, until :noh turns it off
+    pub highlight_search: bool,
+
+    // Text last yanked or deleted, put back with p and P. Whole lines go in line-wise, so they're
+    // put back as lines of their own instead of into the cursor line.
+    register: Vec<Vec<char>>,
+    linewise: bool,
+
+    // Set by "+ or "* for the command typed after it, which then yanks to the system clipboard
+    // and puts from it, as every command does with clipboard=unnamed
+    pub clipboard_register: bool,
+
+    // What visual mode has selected, highlighted while it's there
+    pub selection: Option<Selection>,
+
+    // The selection visual mode was last left with, which the '< and '> marks go by
+    pub last_visual: Option<Selection>,
+
+    // Screen column vertical movement is aiming for, and where it last landed. Moving the cursor
+    // anywhere else makes it stale.
+    sticky_col: Option<(usize, (usize, usize))>,
 
-    prev_cursor_col: Option<u16>,
+    // Id of the current change, grouping its edits so undo takes them back together
+    change: usize,
+
+    // What the screen was last drawn with, None when it has to be redrawn from scratch
+    drawn: Option<Drawn>,
+
+    // The bracket matching the one under the cursor, highlighted in normal mode
+    bracket: Option<(usize, usize)>,
+
+    // The other open buffers in the order they were opened, with `file` sitting at `current`
+    // among them
+    buffers: Vec<Stashed>,
+    current: usize,
+
+    // The buffer that was current before this one, which Ctrl-^ goes back to
+    alternate: Option<usize>,
+
+    // The window the cursor isn't in when the screen is split, onto its own buffer or another
+    // view of the current one
+    split: Option<Window>,
+
+    // Keys of a normal mode command typed so far, like "3d", shown with showcmd
+    pub pending_keys: String,
+}
+
+// A window not being edited: the buffer it shows, which part of it and where its cursor was left
+#[derive(Clone, Copy)]
+pub struct Window {
+    pub buffer: usize,
+    pub start: usize,
+    pub left: usize,
+    pub pos: (usize, u16),
+}
+
+// How things were before incsearch started moving the view, put back once the search is typed
+pub struct SearchPreview {
+    window: Window,
+    search: Option<String>,
+    highlight_search: bool,
+}
+
+// A buffer put away while another is being edited, with its cursor so it comes back as it was left
+struct Stashed {
+    file: Buffer,
+    cursor: Cursor,
+    pos: (usize, u16),
+}
+
+// Columns kept clear at the right of the mode line for the ruler, or for the scroll position
+// alone without it
+const RULER_WIDTH: u16 = 18;
+const SCROLL_WIDTH: u16 = 5;
+
+// Columns just left of those for showcmd, like vim's ten and a space
+const SHOWCMD_WIDTH: u16 = 11;
+
+// Anything that changes every row on screen when it changes, so only a full redraw will do
+#[derive(Clone, PartialEq)]
+struct Drawn {
+    start: usize,
+    left: usize,
+    gutter: u16,
+    cursor_row: u16,
+    search: Option<Vec<char>>,
+    // Only the split's title shows this, so it's redrawn alone when it changes
+    modified: bool,
 }
 
 impl Undo for Editor {
+    // The cursor only moves back if it moved as part of the same change
     fn undo(&mut self) {
+        let group = self.file.last_group(Action::Undo);
         self.file.undo();
-        self.cursor.undo();
+
+        if group.is_some() && self.cursor.history.last_group(Action::Undo) == group {
+            self.cursor.undo();
+
+            // Cursor history holds text columns in file rows, so it's placed here where tabs and
+            // scrolling are known, scrolling back to the change if it's out of view
+            if let Some((row, col)) = self.cursor.restored.take() {
+                self.jump_to(row, col);
+            }
+        }
     }
 }
 
 impl Redo for Editor {
     fn redo(&mut self) {
+        let group = self.file.last_group(Action::Redo);
         self.file.redo();
-        self.cursor.redo();
+
+        if group.is_some() && self.cursor.history.last_group(Action::Redo) == group {
+            self.cursor.redo();
+            if let Some((row, col)) = self.cursor.restored.take() {
+                self.jump_to(row, col);
+            }
+        }
     }
 }
 
@@ -64,19 +175,382 @@ impl Editor {
             Clear(ClearType::All),
             MoveTo(0, 0),
             SetCursorStyle::SteadyBar,
-            Show
+            Show,
+            EnableMouseCapture
         )
         .unwrap();
         print!("\x1b[3J");
-        Self {
+        let mut editor = Self {
             file,
             cursor: Cursor::new(History::<CursorPosition>::new()),
             mode: Mode::Normal,
-            prev_cursor_col: None,
+            settings: Settings::default(),
+            message: None,
+            search: None,
+            highlight_search: false,
+            register: vec![],
+            linewise: true,
+            clipboard_register: false,
+            selection: None,
+            last_visual: None,
+            sticky_col: None,
+            change: 0,
+            drawn: None,
+            bracket: None,
+            buffers: vec![],
+            current: 0,
+            alternate: None,
+            split: None,
+            pending_keys: String::new(),
+        };
+        editor.apply_settings();
+        editor
+    }
+
+    pub fn register(&self) -> &[Vec<char>] {
+        &self.register
+    }
+
+    pub fn register_linewise(&self) -> bool {
+        self.linewise
+    }
+
+    pub fn yank(&mut self, lines: Vec<Vec<char>>, linewise: bool) {
+        if self.uses_clipboard()
+            && let Err(e) = clipboard::copy(&lines, linewise)
+        {
+            self.message = Some(e.to_string());
+        }
+        self.register = lines;
+        self.linewise = linewise;
+    }
+
+    // Fills the register from the system clipboard before a put that goes by it. If that can't be
+    // read, what was last yanked here is put instead.
+    pub fn paste_clipboard(&mut self) {
+        if !self.uses_clipboard() {
+            return;
         }
+        match clipboard::paste() {
+            Ok((lines, linewise)) => (self.register, self.linewise) = (lines, linewise),
+            Err(e) => self.message = Some(e.to_string()),
+        }
+    }
+
+    fn uses_clipboard(&self) -> bool {
+        self.clipboard_register || self.settings.clipboard
+    }
+
+    // Pushes the settings the buffer needs to know about down into it
+    pub fn apply_settings(&mut self) {
+        self.file.set_undolevels(self.settings.undolevels);
+    }
+
+    pub fn open(&mut self, file: Buffer) {
+        self.file = file;
+        self.apply_settings();
+        self.cursor = Cursor::new(History::<CursorPosition>::new());
+        self.sticky_col = None;
+        self.drawn = None;
+        self.bracket = None;
+        self.last_visual = None;
+
+        Cursor::move_to((0, 0));
+        self.render();
+        self.cursor_home();
+    }
+
+    // Every open buffer in order, the current one included
+    pub fn buffers(&self) -> impl Iterator<Item = &Buffer> {
+        let (before, after) = self.buffers.split_at(self.current);
+        before.iter().map(|stashed| &stashed.file).chain([&self.file]).chain(after.iter().map(|stashed| &stashed.file))
+    }
+
+    pub fn buffer_count(&self) -> usize {
+        self.buffers.len() + 1
+    }
+
+    pub fn buffer_index(&self) -> usize {
+        self.current
     }
 
-    pub fn cursor_home(&self) {
+    // Where the cursor is, or was left, in the buffer at `index`
+    pub fn buffer_cursor(&self, index: usize) -> (usize, u16) {
+        match index.cmp(&self.current) {
+            cmp::Ordering::Equal => self.cursor_pos(),
+            cmp::Ordering::Less => self.buffers[index].pos,
+            cmp::Ordering::Greater => self.buffers[index - 1].pos,
+        }
+    }
+
+    // Opens a buffer after the others, keeping the current one to come back to
+    pub fn add_buffer(&mut self, file: Buffer) {
+        let pos = self.cursor_pos();
+        let file = std::mem::replace(&mut self.file, file);
+        let cursor = std::mem::replace(&mut self.cursor, Cursor::new(History::<CursorPosition>::new()));
+        self.buffers.insert(self.current, Stashed { file, cursor, pos });
+        self.alternate = Some(self.current);
+        self.current = self.buffers.len();
+
+        let file = std::mem::take(&mut self.file);
+        self.open(file);
+    }
+
+    pub fn alternate_buffer(&self) -> Option<usize> {
+        self.alternate
+    }
+
+    // Makes the buffer at `index` the current one, putting the cursor back where it was left
+    pub fn switch_buffer(&mut self, index: usize) {
+        if index == self.current || index >= self.buffer_count() {
+            return;
+        }
+
+        let pos = self.take_buffer(index);
+        self.resume(pos);
+    }
+
+    // Swaps the buffer at `index` in for the current one without drawing it, returning where its
+    // cursor was left
+    fn take_buffer(&mut self, index: usize) -> (usize, u16) {
+        // The stashed buffers skip the current one, so those after it are a place further back
+        let pos = self.cursor_pos();
+        let incoming = self.buffers.remove(if index > self.current { index - 1 } else { index });
+        let outgoing = Stashed {
+            file: std::mem::replace(&mut self.file, incoming.file),
+            cursor: std::mem::replace(&mut self.cursor, incoming.cursor),
+            pos,
+        };
+        self.buffers.insert(if index < self.current { self.current - 1 } else { self.current }, outgoing);
+        self.alternate = Some(self.current);
+        self.current = index;
+
+        // The marks were set in the buffer that was left, so they'd point at the wrong lines here
+        self.last_visual = None;
+        incoming.pos
+    }
+
+    // Drops the current buffer for the one before it, or after it if it was first. The last
+    // buffer can't be closed, so false is returned and it's left to quitting to get rid of it.
+    pub fn close_buffer(&mut self) -> bool {
+        if self.buffers.is_empty() {
+            return false;
+        }
+
+        // A window onto the closed buffer goes with it, and the others move up the list
+        let closed = self.current;
+        match self.split.as_mut() {
+            Some(window) if window.buffer == closed => {
+                self.split = None;
+                utils::set_region(Region::Full);
+            }
+            Some(window) if window.buffer > closed => window.buffer -= 1,
+            _ => {}
+        }
+
+        self.alternate = match self.alternate {
+            Some(alternate) if alternate == closed => None,
+            Some(alternate) if alternate > closed => Some(alternate - 1),
+            alternate => alternate,
+        };
+
+        let incoming = self.buffers.remove(closed.saturating_sub(1));
+        self.current = closed.saturating_sub(1);
+        self.last_visual = None;
+        self.file = incoming.file;
+        self.cursor = incoming.cursor;
+        self.resume(incoming.pos);
+        true
+    }
+
+    // Shows a buffer that was just made current, with its cursor where it was left
+    fn resume(&mut self, pos: (usize, u16)) {
+        self.apply_settings();
+        self.selection = None;
+        self.sticky_col = None;
+        self.bracket = None;
+
+        // Restored after the first render, once the gutter is the right width for this buffer.
+        // The window may have got smaller or the buffer shorter since, so it's jumped to.
+        Cursor::move_to((0, 0));
+        self.redraw();
+        self.jump_to(self.file.start + pos.1 as usize, pos.0);
+        self.render();
+    }
+
+    // Splits the screen in two, both windows showing the current buffer and the top one taking
+    // the cursor, or the bottom one with splitbelow. Returns false if the screen is already split.
+    pub fn split_window(&mut self) -> bool {
+        if self.split.is_some() {
+            return false;
+        }
+
+        let region = if self.settings.splitbelow { Region::Bottom } else { Region::Top };
+        self.open_split(self.window(), region);
+        true
+    }
+
+    // Splits the screen with `window` as the one the cursor isn't in, and the current buffer
+    // taking `region`
+    pub fn open_split(&mut self, window: Window, region: Region) {
+        let (row, col) = self.cursor_at();
+        self.split = Some(window);
+        utils::set_region(region);
+
+        // The cursor may have been below what fits in the smaller window
+        self.drawn = None;
+        self.jump_to(row, col);
+        self.redraw();
+    }
+
+    pub fn split(&self) -> Option<Window> {
+        self.split
+    }
+
+    // Moves the cursor into the other window of a split
+    pub fn switch_window(&mut self) {
+        let Some(there) = self.split.take() else {
+            return;
+        };
+        self.split = Some(self.window());
+
+        let other = match utils::region() {
+            Region::Top => Region::Bottom,
+            _ => Region::Top,
+        };
+        self.enter_window(there, other);
+    }
+
+    // Closes the window the cursor is in, the other one getting the whole screen. Returns false
+    // if the screen isn't split.
+    pub fn close_window(&mut self) -> bool {
+        let Some(there) = self.split.take() else {
+            return false;
+        };
+        self.enter_window(there, Region::Full);
+        true
+    }
+
+    // Closes the window the cursor isn't in, the one it is in getting the whole screen. Returns
+    // false if the screen isn't split.
+    pub fn only_window(&mut self) -> bool {
+        if self.split.take().is_none() {
+            return false;
+        }
+
+        // Taken before the region changes, since cursor rows are counted from the window top
+        let (row, col) = self.cursor_at();
+        utils::set_region(Region::Full);
+
+        self.drawn = None;
+        self.jump_to(row, col);
+        self.redraw();
+        true
+    }
+
+    fn enter_window(&mut self, window: Window, region: Region) {
+        if window.buffer != self.current {
+            self.take_buffer(window.buffer);
+        }
+        self.file.start = window.start;
+        self.file.left = window.left;
+
+        utils::set_region(region);
+        self.resume(window.pos);
+    }
+
+    // The window the cursor is in, as it'd be left when moving to the other one
+    fn window(&self) -> Window {
+        Window { buffer: self.current, start: self.file.start, left: self.file.left, pos: self.cursor_pos() }
+    }
+
+    // Draws the window the cursor isn't in, and the status line between the two
+    fn draw_split(&mut self) {
+        let Some(window) = self.split else {
+            return;
+        };
+
+        let focused = utils::region();
+        let other = match focused {
+            Region::Top => Region::Bottom,
+            _ => Region::Top,
+        };
+        utils::set_region(other);
+
+        // It's drawn with its own view onto the buffer, which is put back after
+        let buffer = match window.buffer == self.current {
+            true => &mut self.file,
+            false => &mut self.buffers[if window.buffer > self.current { window.buffer - 1 } else { window.buffer }].file,
+        };
+        let (start, left) = (buffer.start, buffer.left);
+        (buffer.start, buffer.left) = (window.start, window.left);
+        buffer.print(&self.settings, window.pos.1, &Overlay::default());
+        (buffer.start, buffer.left) = (start, left);
+
+        utils::set_region(focused);
+        self.draw_title();
+    }
+
+    // The status line between the windows, named after the top one's buffer
+    fn draw_title(&self) {
+        let Some(window) = self.split else {
+            return;
+        };
+
+        let top = match utils::region() {
+            Region::Top => &self.file,
+            _ => self.buffers().nth(window.buffer).unwrap_or(&self.file),
+        };
+        let name = top.name();
+        let modified = if top.modified { " [+]" } else { "" };
+        let (row, rows) = Region::Top.bounds();
+        let width = size().unwrap().0 as usize;
+
+        execute!(stdout(), MoveTo(0, row + rows)).unwrap();
+        let title = format!("\"{}\"{}", utils::truncate_left(name, width.saturating_sub(modified.len() + 2)), modified);
+        print_bg!(Color::DarkGrey, "{:width$}", title, width = width);
+    }
+
+    // Starts a new change. Edits made until the next one are undone and redone as one step.
+    pub fn begin_change(&mut self) {
+        self.change += 1;
+        self.file.begin_group(self.change);
+        self.cursor.history.begin_group(self.change);
+    }
+
+    // Waits for the next event. With autosave on, the buffer is saved whenever that long goes by
+    // without one.
+    pub fn read_event(&mut self) -> std::io::Result<Event> {
+        while let Some(seconds) = self.settings.autosave {
+            if poll(Duration::from_secs(seconds))? {
+                break;
+            }
+            self.autosave();
+        }
+        read()
+    }
+
+    fn autosave(&mut self) {
+        let buffer = &mut self.file;
+        if buffer.path.is_none() || !buffer.modified || buffer.readonly || buffer.scratch {
+            return;
+        }
+
+        // A failed save is left for the next :w to report
+        if buffer.write().is_ok() {
+            buffer.mark_saved();
+            self.message = Some("Autosaved".to_string());
+            self.render();
+        }
+    }
+
+    // Throws away what's on screen and draws everything again
+    pub fn redraw(&mut self) {
+        self.drawn = None;
+        self.render();
+    }
+
+    pub fn cursor_home(&mut self) {
         let mut row = min(self.file.length(), utils::window_size() as usize);
         if row == self.file.length() && row != 0 {
             row -= 1;
@@ -84,7 +558,7 @@ impl Editor {
 
         if let Some(last_line) = self.file.get_line(row) {
             let col = last_line.len();
-            execute!(stdout(), MoveTo(col as u16, row as u16)).unwrap();
+            self.place_cursor((col, row as u16));
         }
     }
 
@@ -95,16 +569,59 @@ impl Editor {
         }
     }
 
+    // Redraws the rows that changed since the last render, or the whole screen if the view moved
     pub fn render(&mut self) {
-        let (col, row) = position().unwrap();
+        let (col, row) = self.cursor_pos();
+        let gutter = self.file.gutter_width(&self.settings);
+        let search = self.search.as_ref().filter(|_| self.highlight_search).map(|pattern| pattern.chars().collect::<Vec<char>>());
+        let last = self.drawn.replace(Drawn { start: self.file.start, left: self.file.left, gutter, cursor_row: row, search: search.clone(), modified: self.file.modified });
 
         disable_raw_mode().unwrap();
-        execute!(stdout(), Hide, Clear(ClearType::All), MoveTo(0, 0)).unwrap();
-        print!("\x1b[3J");
-        self.file.print();
+        execute!(stdout(), Hide).unwrap();
+
+        // The gutter can change width between renders, so the cursor is put back by text column
+        Cursor::set_gutter(gutter);
+
+        // Both the bracket that was highlighted and the new one need drawing again
+        let bracket = match self.mode {
+            Mode::Normal => self.file.matching_bracket((self.file.start + row as usize, col)),
+            _ => None,
+        };
+        if bracket != self.bracket {
+            for (row, _) in [self.bracket, bracket].into_iter().flatten() {
+                self.file.mark_dirty(row);
+            }
+            self.bracket = bracket;
+        }
+
+        let overlay = Overlay { selection: self.selection.as_ref(), bracket, search: search.as_deref() };
+
+        // A wrapped line taking up another row moves everything below it, so wrapping always
+        // draws the whole screen. So does the other window showing this buffer, to keep up with it.
+        let same_buffer = self.split.is_some_and(|window| window.buffer == self.current);
+        match last {
+            Some(last) if !self.settings.wrap && !same_buffer && last.start == self.file.start && last.left == self.file.left && last.gutter == gutter && last.search == search => {
+                // Concealing depends on which line the cursor is on
+                if last.cursor_row != row {
+                    self.file.mark_dirty(self.file.start + last.cursor_row as usize);
+                    self.file.mark_dirty(self.file.start + row as usize);
+                }
+                self.file.print_dirty(&self.settings, row, &overlay);
+                if last.modified != self.file.modified {
+                    self.draw_title();
+                }
+            }
+            _ => {
+                execute!(stdout(), Clear(ClearType::All), MoveTo(0, 0)).unwrap();
+                print!("\x1b[3J");
+                self.file.print(&self.settings, row, &overlay);
+                self.draw_split();
+            }
+        }
 
         self.set_mode(self.mode);
-        execute!(stdout(), MoveTo(col, row), Show).unwrap();
+        self.place_cursor((col, row));
+        execute!(stdout(), Show).unwrap();
         enable_raw_mode().unwrap();
     }
 
@@ -112,126 +629,617 @@ impl Editor {
         self.mode = mode;
 
         let prev = position().unwrap();
-        execute!(stdout(), MoveTo(0, utils::window_size() + 1)).unwrap();
+        let cursor = self.cursor_pos();
+        execute!(stdout(), MoveTo(0, utils::status_row())).unwrap();
+        utils::clear_line();
 
         self.mode.get().print();
+        self.print_file_info(cursor);
+
+        if let Some(message) = &self.message {
+            print_fg!(Color::Grey, " {}", message);
+        }
+
+        if self.settings.showcmd {
+            self.print_pending_keys();
+        }
+        if self.settings.ruler {
+            self.print_ruler(cursor);
+        }
+        self.print_scroll_position();
 
         execute!(stdout(), MoveTo(prev.0, prev.1)).unwrap();
     }
 
-    pub fn listen(&mut self) {
-        self.mode.get().listen(self);
+    // How many chars are selected, or lines for a line-wise selection
+    fn selection_size(&self) -> Option<usize> {
+        let selection = self.selection?;
+        Some(match selection.kind {
+            SelectionKind::Line => selection.rows().count(),
+            SelectionKind::Char => selection
+                .rows()
+                .map(|row| {
+                    let len = self.line_len(row);
+                    selection.cols(row, len).map_or(0, |cols| cmp::min(cols.end, len).saturating_sub(cols.start))
+                })
+                .sum(),
+        })
     }
 
-    fn move_cursor_up(&mut self, mut cur_pos: (u16, u16)) -> Option<()> {
-        if cur_pos.1 <= 0 {
-            if self.file.move_up() {
-                execute!(stdout(), MoveDown(1)).unwrap();
-                cur_pos = position().unwrap();
-                self.render();
-            } else {
-                return None;
-            }
+    // Columns at the right of the mode line taken by the ruler, scroll position and showcmd
+    fn right_width(&self) -> u16 {
+        let ruler = if self.settings.ruler { RULER_WIDTH } else { SCROLL_WIDTH };
+        let showcmd = if self.settings.showcmd { SHOWCMD_WIDTH } else { 0 };
+        ruler + showcmd
+    }
+
+    // Only the last keys fit when there are more than the space has room for. In visual mode it's
+    // the size of the selection that's shown instead, like vim.
+    fn print_pending_keys(&self) {
+        let col = size().unwrap().0.saturating_sub(self.right_width());
+        let keys: Vec<char> = match self.selection_size() {
+            Some(size) => size.to_string().chars().collect(),
+            None => self.pending_keys.chars().collect(),
+        };
+        let shown: String = keys[keys.len().saturating_sub(SHOWCMD_WIDTH as usize - 1)..].iter().collect();
+
+        execute!(stdout(), MoveTo(col, utils::status_row())).unwrap();
+        print_fg!(Color::Grey, "{}", shown);
+    }
+
+    // Returns whether the buffer may be changed, leaving an error message when it can't
+    pub fn check_modifiable(&mut self) -> bool {
+        if !self.file.modifiable {
+            self.message = Some("Cannot make changes, 'modifiable' is off".to_string());
+        } else if self.file.readonly {
+            self.message = Some("Cannot make changes, 'readonly' is on".to_string());
         }
+        self.file.modifiable && !self.file.readonly
+    }
 
-        let up_pos = (cur_pos.0, cur_pos.1 - 1);
-        let next_line = self.file.get_line(up_pos.1 as usize)?;
+    // Reports how far an undo or redo moved through the history, like vim's "1 change; before #3".
+    // Changes are counted in groups, since that's what one undo takes back.
+    pub fn report_undo(&mut self, prev_groups: usize, action: Action) {
+        let groups = self.file.history_groups();
+        let changes = prev_groups.abs_diff(groups);
+        let plural = if changes == 1 { "change" } else { "changes" };
 
-        if let Some(_) = next_line.get(up_pos.0 as usize) {
-            if let Some(prev_cursor_col) = self.prev_cursor_col {
-                execute!(
-                    stdout(),
-                    MoveToPreviousLine(1),
-                    MoveRight(min(prev_cursor_col, next_line.len() as u16))
-                )
-                .unwrap();
-                self.prev_cursor_col = None;
-            } else {
-                execute!(stdout(), MoveUp(1)).unwrap();
-            }
-        } else if next_line.is_empty() {
-            self.prev_cursor_col = Some(cur_pos.0);
-            execute!(stdout(), MoveToPreviousLine(1)).unwrap();
-        } else {
-            execute!(
-                stdout(),
-                MoveToPreviousLine(1),
-                MoveRight(next_line.len() as u16)
-            )
-            .unwrap();
+        self.message = Some(match (action, changes) {
+            (Action::Undo, 0) => "Already at oldest change".to_string(),
+            (_, 0) => "Already at newest change".to_string(),
+            (Action::Undo, _) => format!("{} {}; before #{}", changes, plural, prev_groups),
+            (_, _) => format!("{} {}; after #{}", changes, plural, groups),
+        });
+    }
+
+    // Leaves `message` for the user when more lines than the report option allows for changed,
+    // like vim's "5 fewer lines"
+    pub fn report_lines(&mut self, lines: usize, message: String) {
+        if lines > self.settings.report {
+            self.message = Some(message);
         }
+    }
 
-        Some(())
+    // The file name, whether it has unsaved changes, how many lines it has, which of the open
+    // buffers it is and where the cursor is, like `"main.rs" [+] 120L [2/3] 14,5`. The position is
+    // left to the ruler when that's on. Long paths lose their start so the name itself stays in view.
+    fn print_file_info(&self, cursor: (usize, u16)) {
+        let modified = match (self.file.modified, self.settings.changecount) {
+            (false, _) => String::new(),
+            (true, true) => match self.file.unsaved_changes() {
+                Some(changes) => format!(" [+{}]", changes),
+                None => " [+]".to_string(),
+            },
+            (true, false) => " [+]".to_string(),
+        };
+        let format = match self.file.line_ending {
+            LineEnding::Dos => " [dos]",
+            LineEnding::Unix => "",
+        };
+        let binary = if self.file.binary { " [binary]" } else { "" };
+        let eol = match self.file.final_newline || self.file.fixeol {
+            true => "",
+            false => " [noeol]",
+        };
+        let location = match self.settings.ruler {
+            true => String::new(),
+            false => format!(" {}", self.location(cursor)),
+        };
+        let lines = format!("{}{}{} {}L [{}/{}]{}", binary, eol, format, self.file.length(), self.current + 1, self.buffer_count(), location);
+
+        // Whatever is left between the mode name and the ruler
+        let used = position().unwrap().0 as usize + modified.chars().count() + lines.chars().count() + 3;
+        let room = (size().unwrap().0 as usize).saturating_sub(used + self.right_width() as usize);
+
+        let name = self.file.name();
+        print_fg!(Color::Grey, " \"{}\"{}{}", utils::truncate_left(name, room), modified, lines);
     }
 
-    pub fn move_cursor_down(&mut self, mut cur_pos: (u16, u16)) -> Option<()> {
-        if cur_pos.1 >= utils::window_size() {
-            if self.file.move_down() {
-                execute!(stdout(), MoveUp(1)).unwrap();
-                cur_pos = position().unwrap();
-                self.render();
-            }
+    // The cursor's 1-based line and column, like vim's `14,5`, or `14,0-1` on an empty line
+    fn location(&self, pos: (usize, u16)) -> String {
+        let row = self.file.start + pos.1 as usize + 1;
+        match self.file.get_line(pos.1 as usize) {
+            Some(line) if !line.is_empty() => format!("{},{}", row, pos.0 + 1),
+            _ => format!("{},0-1", row),
         }
+    }
 
-        let down_pos = (cur_pos.0, cur_pos.1 + 1);
-        let next_line = self.file.get_line(down_pos.1 as usize)?;
+    fn print_ruler(&self, pos: (usize, u16)) {
+        // Leave room to the right of the ruler like vim does
+        let ruler_col = size().unwrap().0.saturating_sub(RULER_WIDTH);
+        execute!(stdout(), MoveTo(ruler_col, utils::status_row())).unwrap();
+        print_fg!(Color::Grey, "{}", self.location(pos));
+    }
 
-        if let Some(_) = next_line.get(down_pos.0 as usize) {
-            if let Some(prev_cursor_col) = self.prev_cursor_col {
-                execute!(
-                    stdout(),
-                    MoveToNextLine(1),
-                    MoveRight(min(prev_cursor_col, next_line.len() as u16))
-                )
-                .unwrap();
-                self.prev_cursor_col = None;
-            } else {
-                execute!(stdout(), MoveDown(1)).unwrap();
-            }
-        } else if next_line.is_empty() {
-            self.prev_cursor_col = Some(cur_pos.0);
-            execute!(stdout(), MoveToNextLine(1)).unwrap();
+    // Where the screen is in the file, as vim's All, Top, Bot or how far down it is
+    fn print_scroll_position(&self) {
+        let visible = utils::window_size() as usize + 1;
+        let length = self.file.length();
+        let start = self.file.start;
+
+        let position = match (start == 0, start + visible >= length) {
+            (true, true) => "All".to_string(),
+            (true, false) => "Top".to_string(),
+            (false, true) => "Bot".to_string(),
+            (false, false) => format!("{}%", start * 100 / (length - visible)),
+        };
+
+        execute!(stdout(), MoveTo(size().unwrap().0.saturating_sub(4), utils::status_row())).unwrap();
+        print_fg!(Color::Grey, "{}", position);
+    }
+
+    pub fn listen(&mut self) {
+        self.mode.get().listen(self);
+    }
+
+    // Moves to the row above or below, aiming for the column the cursor was on when it started
+    // moving up and down. Landing on a shorter line doesn't lose that column, and it's only
+    // forgotten once the cursor is moved some other way or the text is edited. With wrap on,
+    // `by_screen_row` steps between the rows of a wrapped line rather than over the whole line.
+    fn move_cursor_vertically(&mut self, up: bool, by_screen_row: bool) -> Option<()> {
+        let (row, col) = self.cursor_at();
+        let visual = utils::visual_col(self.file.get_line_at(row)?, col, self.settings.tabstop);
+        let desired = match self.sticky_col {
+            Some((desired, landed)) if landed == (row, col) => desired,
+            _ => visual,
+        };
+
+        // Which row of the line to land on, and the column that puts the cursor at on screen
+        let width = cmp::max(utils::text_width(), 1) as usize;
+        let height = |row| self.file.line_height(row, &self.settings, width);
+        let (target, visual) = if by_screen_row && self.settings.wrap {
+            let segment = visual / width;
+            let (target, segment) = match up {
+                true if segment > 0 => (row, segment - 1),
+                true => (row.checked_sub(1)?, height(row.checked_sub(1)?) - 1),
+                false if segment + 1 < height(row) => (row, segment + 1),
+                false => (row + 1, 0),
+            };
+            (target, segment * width + desired % width)
         } else {
-            execute!(
-                stdout(),
-                MoveToNextLine(1),
-                MoveRight(next_line.len() as u16)
-            )
-            .unwrap();
-        }
+            let target = match up {
+                true => row.checked_sub(1)?,
+                false => row + 1,
+            };
+            (target, desired)
+        };
+        let line = self.file.get_line_at(target)?;
+        let col = cmp::min(utils::logical_col(line, visual, self.settings.tabstop), line.len());
+
+        self.jump_to(target, col);
+        self.sticky_col = Some((desired, self.cursor_at()));
         Some(())
     }
 
-    fn move_cursor_left(&mut self, cur_pos: (u16, u16)) -> Option<()> {
-        if cur_pos.0 <= 0 {
+    // Moves to the line above or below like gj and gk, past every row a wrapped line takes up
+    pub fn move_logical_line(&mut self, up: bool) -> Option<()> {
+        self.move_cursor_vertically(up, false)
+    }
+
+    fn move_cursor_left(&mut self, cur_pos: (usize, u16)) -> Option<()> {
+        if cur_pos.0 == 0 {
             return None;
         }
 
         let left_pos = (cur_pos.0 - 1, cur_pos.1);
         self.file.get_line(left_pos.1 as usize)?;
 
-        execute!(stdout(), MoveLeft(1)).unwrap();
+        self.shift_cursor(-1);
         Some(())
     }
 
-    fn move_cursor_right(&mut self, cur_pos: (u16, u16)) -> Option<()> {
+    fn move_cursor_right(&mut self, cur_pos: (usize, u16)) -> Option<()> {
         let right_pos = (cur_pos.0 + 1, cur_pos.1);
         let current_line = self.file.get_line(right_pos.1 as usize)?;
 
-        if right_pos.0 <= current_line.len() as u16 {
-            execute!(stdout(), MoveRight(1)).unwrap();
+        if right_pos.0 <= current_line.len() {
+            self.shift_cursor(1);
         }
         Some(())
     }
 
+    // Moves the cursor to an absolute row and column, scrolling the view if the row is off screen
+    pub fn jump_to(&mut self, row: usize, col: usize) {
+        let row = min(row, self.file.length() - 1);
+        let col = min(col, self.line_len(row));
+        let height = utils::window_size() as usize + 1;
+
+        // Scroll early enough to keep scrolloff lines around the cursor, but never so far that
+        // the window runs past the end of the file
+        let margin = min(self.settings.scrolloff, (height - 1) / 2);
+        let (start, left) = (self.file.start, self.file.left);
+        if row < start + margin {
+            self.file.start = row.saturating_sub(margin);
+        } else if row + margin >= start + height {
+            let bottom = min(row + margin + 1 - height, self.file.length().saturating_sub(height));
+            self.file.start = cmp::max(start, bottom);
+        }
+
+        self.place_cursor((col, (row - self.file.start) as u16));
+
+        if self.file.start != start || self.file.left != left {
+            self.render();
+        }
+    }
+
+    // Moves the cursor onto the char under a click, or the end of the line if it's past that.
+    // Clicks outside the window the cursor is in are ignored.
+    pub fn click(&mut self, column: u16, row: u16) {
+        let top = utils::window_top();
+        if row < top || row > top + utils::window_size() {
+            return;
+        }
+
+        // Going through the screen cursor accounts for the gutter, tabs, scrolling and wrapping
+        Cursor::move_to((column.saturating_sub(Cursor::gutter()), row - top));
+        let (row, col) = self.cursor_at();
+        self.jump_to(row, col);
+    }
+
+    // Scrolls the view a few lines, taking the cursor along only if it would go off screen
+    pub fn scroll(&mut self, down: bool) {
+        const LINES: usize = 3;
+
+        let (row, col) = self.cursor_at();
+        let last = self.file.length() - 1;
+        self.file.start = match down {
+            true => min(self.file.start + LINES, last),
+            false => self.file.start.saturating_sub(LINES),
+        };
+
+        let bottom = self.file.start + utils::window_size() as usize;
+        let row = row.clamp(self.file.start, min(bottom, last));
+        let col = min(col, self.line_len(row));
+        self.place_cursor((col, (row - self.file.start) as u16));
+    }
+
+    pub fn move_word_forward(&mut self) {
+        let (mut row, mut col) = self.cursor_at();
+
+        // Skip the rest of the word under the cursor
+        if let Some(c) = self.char_at(row, col) {
+            let class = utils::char_class(c);
+            if class != utils::CharClass::Whitespace {
+                while self.char_at(row, col).is_some_and(|c| utils::char_class(c) == class) {
+                    col += 1;
+                }
+            }
+        }
+
+        // Then any whitespace, wrapping onto the following lines. An empty line counts as a word.
+        loop {
+            match self.char_at(row, col) {
+                Some(c) if !c.is_whitespace() => break,
+                Some(_) => col += 1,
+                None if row + 1 >= self.file.length() => {
+                    col = self.line_len(row).saturating_sub(1);
+                    break;
+                }
+                None => {
+                    row += 1;
+                    col = 0;
+                    if self.line_len(row) == 0 {
+                        break;
+                    }
+                }
+            }
+        }
+
+        self.jump_to(row, col);
+    }
+
+    pub fn move_word_back(&mut self) {
+        let (mut row, mut col) = self.cursor_at();
+
+        // Step back at least once and past any whitespace, wrapping onto the previous lines
+        loop {
+            if col > 0 {
+                col -= 1;
+            } else if row > 0 {
+                row -= 1;
+                col = self.line_len(row).saturating_sub(1);
+
+                // An empty line counts as a word
+                if self.line_len(row) == 0 {
+                    break;
+                }
+            } else {
+                break;
+            }
+
+            if self.char_at(row, col).is_some_and(|c| !c.is_whitespace()) {
+                break;
+            }
+        }
+
+        // Then back to the start of that word
+        if let Some(c) = self.char_at(row, col) {
+            let class = utils::char_class(c);
+            while col > 0 && self.char_at(row, col - 1).is_some_and(|c| utils::char_class(c) == class) {
+                col -= 1;
+            }
+        }
+
+        self.jump_to(row, col);
+    }
+
+    pub fn move_word_end(&mut self) {
+        let (mut row, mut col) = self.cursor_at();
+
+        // Step forward at least once and past any whitespace, wrapping onto the following lines
+        loop {
+            if col + 1 < self.line_len(row) {
+                col += 1;
+            } else if row + 1 < self.file.length() {
+                row += 1;
+                col = 0;
+            } else {
+                return;
+            }
+
+            if self.char_at(row, col).is_some_and(|c| !c.is_whitespace()) {
+                break;
+            }
+        }
+
+        // Then forward to the last char of that word
+        if let Some(c) = self.char_at(row, col) {
+            let class = utils::char_class(c);
+            while self.char_at(row, col + 1).is_some_and(|c| utils::char_class(c) == class) {
+                col += 1;
+            }
+        }
+
+        self.jump_to(row, col);
+    }
+
+    pub fn move_line_start(&mut self) {
+        let (row, _) = self.cursor_at();
+        self.jump_to(row, 0);
+    }
+
+    pub fn move_first_non_blank(&mut self) {
+        let (row, _) = self.cursor_at();
+        let col = self.file.get_line_at(row).map_or(0, |line| utils::first_non_blank(line));
+        self.jump_to(row, col);
+    }
+
+    // Jumps to the bracket matching the one under the cursor. Off a bracket, the next one along
+    // the line is used, like vim's %.
+    pub fn move_matching_bracket(&mut self) {
+        let (row, col) = self.cursor_at();
+        let line = self.file.get_line_at(row).map_or(&[][..], |line| &line[..]);
+
+        let is_bracket = |c: &char| "(){}[]".contains(*c);
+        let Some(from) = line.iter().skip(col).position(is_bracket).map(|offset| col + offset) else {
+            self.message = Some("No bracket on this line".to_string());
+            return;
+        };
+
+        match self.file.matching_bracket((row, from)) {
+            Some((row, col)) => self.jump_to(row, col),
+            None => self.message = Some("No match".to_string()),
+        }
+    }
+
+    // Lands on the last char of the line rather than past it, like vim's normal mode
+    pub fn move_line_end(&mut self) {
+        let (row, _) = self.cursor_at();
+        self.jump_to(row, self.line_len(row).saturating_sub(1));
+    }
+
+    // Jumps to the next match of the last search, wrapping around the ends of the buffer
+    pub fn search_next(&mut self, forward: bool) -> Result<(), RunError> {
+        let pattern: Vec<char> = self.search.as_ref().ok_or(RunError::NoPattern)?.chars().collect();
+        self.highlight_search = true;
+        if self.file.is_empty() {
+            return Err(RunError::PatternNotFound);
+        }
+        let (row, col) = self.cursor_at();
+
+        let found = match forward {
+            true => self.find_forward(&pattern, row, col),
+            false => self.find_back(&pattern, row, col),
+        };
+
+        let (row, col) = found.ok_or(RunError::PatternNotFound)?;
+        self.jump_to(row, col);
+        Ok(())
+    }
+
+    pub fn begin_preview(&self) -> SearchPreview {
+        SearchPreview { window: self.window(), search: self.search.clone(), highlight_search: self.highlight_search }
+    }
+
+    // Shows the first match of `pattern` after where the search started, as it's being typed.
+    // Without one the view goes back to how it was.
+    pub fn preview_search(&mut self, pattern: &str, preview: &SearchPreview) {
+        let chars: Vec<char> = pattern.chars().collect();
+        let (row, col) = (preview.window.start + preview.window.pos.1 as usize, preview.window.pos.0);
+        let found = match chars.is_empty() || self.file.is_empty() {
+            true => None,
+            false => self.find_forward(&chars, row, col),
+        };
+
+        (self.file.start, self.file.left) = (preview.window.start, preview.window.left);
+        match found {
+            Some((row, col)) => {
+                self.search = Some(pattern.to_string());
+                self.highlight_search = true;
+                self.jump_to(row, col);
+            }
+            None => {
+                self.search = preview.search.clone();
+                self.highlight_search = preview.highlight_search;
+                self.place_cursor(preview.window.pos);
+            }
+        }
+        self.render();
+    }
+
+    // Puts back the view and the last search, so the typed one is searched for from the start
+    pub fn end_preview(&mut self, preview: SearchPreview) {
+        (self.file.start, self.file.left) = (preview.window.start, preview.window.left);
+        self.search = preview.search;
+        self.highlight_search = preview.highlight_search;
+        self.place_cursor(preview.window.pos);
+        self.render();
+    }
+
+    // Both searches visit the cursor line twice, the second time for the part they skipped first
+    fn find_forward(&self, pattern: &[char], row: usize, col: usize) -> Option<(usize, usize)> {
+        let length = self.file.length();
+        for i in 0..=length {
+            let r = (row + i) % length;
+            let line = self.file.get_line_at(r)?;
+            let from = if i == 0 { col + 1 } else { 0 };
+
+            if let Some(c) = utils::find_chars(line, pattern, from, self.settings.ignore_case(pattern))
+                && (i < length || c <= col)
+            {
+                return Some((r, c));
+            }
+        }
+        None
+    }
+
+    fn find_back(&self, pattern: &[char], row: usize, col: usize) -> Option<(usize, usize)> {
+        let length = self.file.length();
+        for i in 0..=length {
+            let r = (row + length - i % length) % length;
+            let line = self.file.get_line_at(r)?;
+            let before = if i == 0 { col } else { line.len() };
+
+            if let Some(c) = utils::rfind_chars(line, pattern, before, self.settings.ignore_case(pattern))
+                && (i < length || c >= col)
+            {
+                return Some((r, c));
+            }
+        }
+        None
+    }
+
+    // The cursor's screen row and the index of the char it's on, which is behind its screen
+    // column once the line has a tab before it or the view is scrolled sideways
+    pub fn cursor_pos(&self) -> (usize, u16) {
+        if self.settings.wrap {
+            return self.wrapped_cursor_pos();
+        }
+
+        let (visual, row) = Cursor::pos();
+        let visual = visual as usize + self.file.left;
+        let col = match self.file.get_line(row as usize) {
+            Some(line) => utils::logical_col(line, visual, self.settings.tabstop),
+            None => visual,
+        };
+        (col, row)
+    }
+
+    // Moves the cursor onto a char index and screen row, the opposite of cursor_pos. The view
+    // scrolls sideways if that's off screen, and is drawn again on the next render.
+    pub fn place_cursor(&mut self, pos: (usize, u16)) {
+        let visual = match self.file.get_line(pos.1 as usize) {
+            Some(line) => utils::visual_col(line, pos.0, self.settings.tabstop),
+            None => pos.0,
+        };
+
+        if self.settings.wrap {
+            return self.place_wrapped_cursor(pos.1 as usize, visual);
+        }
+
+        let width = cmp::max(utils::text_width(), 1) as usize;
+        self.file.left = utils::follow_col(self.file.left, visual, width);
+        Cursor::move_to(((visual - self.file.left) as u16, pos.1));
+    }
+
+    // With wrap on, the screen row is counted down through every row the lines above take up
+    fn wrapped_cursor_pos(&self) -> (usize, u16) {
+        let (screen_col, screen_row) = Cursor::pos();
+        let width = cmp::max(utils::text_width(), 1) as usize;
+
+        let mut row = self.file.start;
+        let mut top = 0;
+        while row + 1 < self.file.length() {
+            let height = self.file.line_height(row, &self.settings, width);
+            if (screen_row as usize) < top + height {
+                break;
+            }
+            top += height;
+            row += 1;
+        }
+
+        let visual = (screen_row as usize).saturating_sub(top) * width + screen_col as usize;
+        let col = match self.file.get_line_at(row) {
+            Some(line) => utils::logical_col(line, visual, self.settings.tabstop),
+            None => visual,
+        };
+        (col, (row - self.file.start) as u16)
+    }
+
+    // Scrolls down a line at a time until the row the cursor wraps onto is on screen
+    fn place_wrapped_cursor(&mut self, row: usize, visual: usize) {
+        let width = cmp::max(utils::text_width(), 1) as usize;
+        let height = utils::window_size() as usize + 1;
+        let row = self.file.start + row;
+        self.file.left = 0;
+
+        let screen_row = |file: &Buffer| -> usize {
+            (file.start..row).map(|row| file.line_height(row, &self.settings, width)).sum::<usize>() + visual / width
+        };
+        while self.file.start < row && screen_row(&self.file) >= height {
+            self.file.start += 1;
+        }
+        Cursor::move_to(((visual % width) as u16, screen_row(&self.file) as u16));
+    }
+
+    // Moves the cursor along its line by a number of chars, so a tab is crossed in one step
+    pub fn shift_cursor(&mut self, by: isize) {
+        let (col, row) = self.cursor_pos();
+        self.place_cursor((col.saturating_add_signed(by), row));
+    }
+
+    // The cursor's absolute row and column in the buffer
+    pub fn cursor_at(&self) -> (usize, usize) {
+        let (col, row) = self.cursor_pos();
+        (self.file.start + row as usize, col)
+    }
+
+    fn char_at(&self, row: usize, col: usize) -> Option<char> {
+        self.file.get_line_at(row).and_then(|line| line.get(col)).copied()
+    }
+
+    fn line_len(&self, row: usize) -> usize {
+        self.file.get_line_at(row).map_or(0, |line| line.len())
+    }
+
     pub fn move_cursor(&mut self, dir: Direction) -> Option<()> {
-        let cur_pos = position().unwrap();
+        let cur_pos = self.cursor_pos();
 
         match dir {
             Direction::Left => self.move_cursor_left(cur_pos),
             Direction::Right => self.move_cursor_right(cur_pos),
-            Direction::Up => self.move_cursor_up(cur_pos),
-            Direction::Down => self.move_cursor_down(cur_pos),
+            Direction::Up => self.move_cursor_vertically(true, true),
+            Direction::Down => self.move_cursor_vertically(false, true),
         }
     }
 }
\ No newline at end of file
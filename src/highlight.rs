@@ -0,0 +1,159 @@
+use std::{ops::Range, path::Path};
+
+use crossterm::style::Color;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Language {
+    Rust,
+}
+
+impl Language {
+    // Picked from the file extension when a buffer is opened
+    pub fn from_path(path: &str) -> Option<Self> {
+        match Path::new(path).extension().and_then(|ext| ext.to_str()) {
+            Some("rs") => Some(Self::Rust),
+            _ => None,
+        }
+    }
+
+    // The name :set syntax= takes
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "rust" => Some(Self::Rust),
+            _ => None,
+        }
+    }
+
+    fn keywords(&self) -> &'static [&'static str] {
+        match self {
+            Self::Rust => &[
+                "as", "async", "await", "break", "const", "continue", "crate", "dyn", "else", "enum", "extern",
+                "false", "fn", "for", "if", "impl", "in", "let", "loop", "match", "mod", "move", "mut", "pub",
+                "ref", "return", "self", "Self", "static", "struct", "super", "trait", "true", "type", "unsafe",
+                "use", "where", "while",
+            ],
+        }
+    }
+}
+
+// Chars of a line drawn in a color of their own
+#[derive(Debug, Clone, PartialEq)]
+pub struct Span {
+    pub range: Range<usize>,
+    pub color: Color,
+}
+
+const KEYWORD: Color = Color::Magenta;
+const STRING: Color = Color::Green;
+const COMMENT: Color = Color::DarkGrey;
+const NUMBER: Color = Color::Cyan;
+
+// Finds the keywords, strings, comments and numbers on a line. Each line is looked at on its own,
+// so strings and comments spanning several lines are only colored on their first.
+pub fn highlight_line(line: &[char], language: Language) -> Vec<Span> {
+    let mut spans = Vec::new();
+
+    let mut i = 0;
+    while i < line.len() {
+        let c = line[i];
+        let start = i;
+
+        if c == '/' && line.get(i + 1) == Some(&'/') {
+            spans.push(Span { range: i..line.len(), color: COMMENT });
+            break;
+        } else if c == '"' {
+            i = string_end(line, i + 1, '"');
+            spans.push(Span { range: start..i, color: STRING });
+        } else if c == '\'' && let Some(end) = char_literal_end(line, i) {
+            i = end;
+            spans.push(Span { range: start..i, color: STRING });
+        } else if c.is_ascii_digit() {
+            i += word_len(line, i);
+            spans.push(Span { range: start..i, color: NUMBER });
+        } else if is_word_char(c) {
+            i += word_len(line, i);
+            let word: String = line[start..i].iter().collect();
+            if language.keywords().contains(&word.as_str()) {
+                spans.push(Span { range: start..i, color: KEYWORD });
+            }
+        } else {
+            i += 1;
+        }
+    }
+    spans
+}
+
+fn is_word_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_'
+}
+
+fn word_len(line: &[char], start: usize) -> usize {
+    line[start..].iter().take_while(|c| is_word_char(**c)).count()
+}
+
+// Just past the quote closing a string that starts at `from`, or the line end if it isn't closed
+fn string_end(line: &[char], from: usize, quote: char) -> usize {
+    let mut i = from;
+    while i < line.len() {
+        match line[i] {
+            '\\' => i += 2,
+            c if c == quote => return i + 1,
+            _ => i += 1,
+        }
+    }
+    line.len()
+}
+
+// A char literal like 'a' or '\n', as opposed to a lifetime like 'a which is never closed
+fn char_literal_end(line: &[char], start: usize) -> Option<usize> {
+    let len = match line.get(start + 1)? {
+        // The escaped char can be a quote itself, so the closing one comes after it
+        '\\' => line.get(start + 3..)?.iter().position(|c| *c == '\'')? + 3,
+        _ => 2,
+    };
+    (line.get(start + len) == Some(&'\'')).then_some(start + len + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The text of every span with its color, easier to read than char ranges
+    fn highlight(line: &str) -> Vec<(String, Color)> {
+        let chars: Vec<char> = line.chars().collect();
+        highlight_line(&chars, Language::Rust)
+            .into_iter()
+            .map(|span| (chars[span.range].iter().collect(), span.color))
+            .collect()
+    }
+
+    fn span(text: &str, color: Color) -> (String, Color) {
+        (text.to_string(), color)
+    }
+
+    #[test]
+    fn keywords_and_numbers() {
+        assert_eq!(highlight("let x = 42;"), [span("let", KEYWORD), span("42", NUMBER)]);
+        assert_eq!(highlight("letter lets"), []);
+    }
+
+    #[test]
+    fn strings_keep_escaped_quotes() {
+        assert_eq!(highlight(r#"f("a \" fn", 1)"#), [span(r#""a \" fn""#, STRING), span("1", NUMBER)]);
+        assert_eq!(highlight("\"open"), [span("\"open", STRING)]);
+    }
+
+    #[test]
+    fn char_literals_but_not_lifetimes() {
+        assert_eq!(highlight("'a'"), [span("'a'", STRING)]);
+        assert_eq!(highlight(r"'\''"), [span(r"'\''", STRING)]);
+        assert_eq!(highlight("&'a str"), []);
+        assert_eq!(highlight("fn f<'a>(x: &'a str)"), [span("fn", KEYWORD)]);
+    }
+
+    #[test]
+    fn comments_run_to_the_line_end() {
+        assert_eq!(highlight("x // let \"y\""), [span("// let \"y\"", COMMENT)]);
+        assert_eq!(highlight("\"//\" x"), [span("\"//\"", STRING)]);
+    }
+}
@@ -14,35 +14,179 @@ pub trait Redo {
 }
 
 pub struct History<T> {
-    edits: Vec<T>,
-    undos: Vec<T>
+    // Each event is tagged with the group it was made in, so a whole group can be undone at once
+    edits: Vec<(usize, T)>,
+    undos: Vec<(usize, T)>,
+    group: usize,
+
+    // How many groups `edits` holds, kept as it changes so trimming doesn't count them every time
+    groups: usize,
+
+    // Most groups kept around for undo, the oldest are dropped whole past this
+    limit: Option<usize>,
+}
+
+impl<T: Clone> Default for History<T> {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl<T: Clone> History<T> {
     pub fn new() -> Self {
-        Self { edits: vec![], undos: vec![]}
+        Self { edits: vec![], undos: vec![], group: 0, groups: 0, limit: None }
+    }
+
+    // Lowering the limit drops the oldest groups straight away, returning how many edits went
+    pub fn set_limit(&mut self, limit: Option<usize>) -> usize {
+        self.limit = limit;
+        self.trim()
+    }
+
+    // Events done from now on belong to `group` until the next call
+    pub fn begin_group(&mut self, group: usize) {
+        self.group = group;
     }
 
-    pub fn update(&mut self, event: T, action: Action) {
+    // Returns how many of the oldest edits were dropped to stay within the limit
+    pub fn update(&mut self, event: T, action: Action) -> usize {
         match action {
-            Action::Do => self.edits.push(event),
+            Action::Do => {
+                // A new edit branches off from here, so the undone ones can't be redone anymore
+                self.undos.clear();
+                self.push_edit((self.group, event));
+            }
             Action::Undo => {
-                if let Some(prev_edit) = self.edits.pop() {
+                if let Some(prev_edit) = self.pop_edit() {
                     self.undos.push(prev_edit);
                 }
             },
             Action::Redo => {
                 if let Some(prev_undo) = self.undos.pop() {
-                    self.edits.push(prev_undo);
+                    self.push_edit(prev_undo);
                 }
             },
         } 
+
+        self.trim()
+    }
+
+    fn push_edit(&mut self, edit: (usize, T)) {
+        if self.edits.last().is_none_or(|(group, _)| *group != edit.0) {
+            self.groups += 1;
+        }
+        self.edits.push(edit);
+    }
+
+    fn pop_edit(&mut self) -> Option<(usize, T)> {
+        let edit = self.edits.pop()?;
+        if self.edits.last().is_none_or(|(group, _)| *group != edit.0) {
+            self.groups -= 1;
+        }
+        Some(edit)
+    }
+
+    fn trim(&mut self) -> usize {
+        let Some(limit) = self.limit else {
+            return 0;
+        };
+
+        // The oldest groups go whole, so undo never takes back only part of a change
+        let mut first = 0;
+        while self.groups > limit {
+            let group = self.edits[first].0;
+            while self.edits.get(first).is_some_and(|(g, _)| *g == group) {
+                first += 1;
+            }
+            self.groups -= 1;
+        }
+
+        self.edits.drain(..first);
+        first
+    }
+
+    pub fn depth(&self) -> usize {
+        self.edits.len()
+    }
+
+    // Groups that can still be undone, each of them one change as far as the user is concerned
+    pub fn groups(&self) -> usize {
+        self.groups
     }
 
     pub fn last_from(&self, action: Action) -> Option<T> {
+        self.last_entry(action).map(|(_, event)| event.clone())
+    }
+
+    // The group the next undo or redo would take from
+    pub fn last_group(&self, action: Action) -> Option<usize> {
+        self.last_entry(action).map(|(group, _)| *group)
+    }
+
+    fn last_entry(&self, action: Action) -> Option<&(usize, T)> {
         match action {
-            Action::Do | Action::Redo => self.undos.last().cloned(),
-            Action::Undo => self.edits.last().cloned(),
+            Action::Do | Action::Redo => self.undos.last(),
+            Action::Undo => self.edits.last(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn history(groups: &[usize]) -> History<usize> {
+        let mut history = History::new();
+        for (i, group) in groups.iter().enumerate() {
+            history.begin_group(*group);
+            history.update(i, Action::Do);
         }
+        history
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn limit_drops_oldest_groups_whole() {
+        let mut history = history(&[1, 1, 2, 3, 3, 3]);
+        assert_eq!(history.set_limit(Some(2)), 2);
+        assert_eq!(history.depth(), 4);
+        assert_eq!(history.last_group(Action::Undo), Some(3));
+
+        history.begin_group(4);
+        assert_eq!(history.update(6, Action::Do), 1);
+        assert_eq!(history.depth(), 4);
+    }
+
+    #[test]
+    fn zero_limit_keeps_nothing() {
+        let mut history = history(&[1, 2]);
+        assert_eq!(history.set_limit(Some(0)), 2);
+        assert_eq!(history.last_from(Action::Undo), None);
+    }
+
+    #[test]
+    fn undone_groups_dont_count() {
+        let mut history = history(&[1, 2, 2]);
+        history.set_limit(Some(2));
+        history.update(0, Action::Undo);
+        history.update(0, Action::Undo);
+
+        // Group 2 is gone from the edits, so a new group fits alongside group 1
+        history.begin_group(3);
+        assert_eq!(history.update(3, Action::Do), 0);
+        assert_eq!(history.depth(), 2);
+    }
+
+    #[test]
+    fn new_edit_drops_what_was_undone() {
+        let mut history = history(&[1]);
+        history.update(0, Action::Undo);
+
+        history.begin_group(2);
+        history.update(1, Action::Do);
+        assert_eq!(history.last_from(Action::Redo), None);
+
+        history.update(0, Action::Redo);
+        assert_eq!(history.depth(), 1);
+        assert_eq!(history.last_from(Action::Undo), Some(1));
+    }
+}
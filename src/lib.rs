@@ -6,6 +6,13 @@ mod utils;
 mod mode;
 mod history;
 mod cursor;
+mod settings;
+mod range;
+mod conceal;
+mod session;
+mod selection;
+mod highlight;
+mod clipboard;
 
 pub use buffer::*;
 pub use command::*;
@@ -14,3 +21,9 @@ pub use utils::*;
 pub use mode::*;
 pub use history::*;
 pub use cursor::*;
+pub use settings::*;
+pub use range::*;
+pub use conceal::*;
+pub use session::*;
+pub use selection::*;
+pub use highlight::*;
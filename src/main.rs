@@ -1,23 +1,58 @@
 use clap::Parser;
-use std::path::Path;
-use vision::{Buffer, Editor};
+use std::{fs, io::{stdin, IsTerminal}, path::Path, process::exit};
+use vision::{Buffer, Editor, Session};
 
 #[derive(Parser)]
 struct Args {
     path_str: Option<String>,
+
+    /// Restore a session saved with :mksession
+    #[arg(long)]
+    session: Option<String>,
 }
 
 fn run(args: Args) {
+    if let Some(session_path) = args.session {
+        let session = fs::read_to_string(&session_path).unwrap_or_else(|e| {
+            eprintln!("vision: cannot read {}: {}", session_path, e);
+            exit(1);
+        });
+        let session = session.parse::<Session>().unwrap_or_else(|e| {
+            eprintln!("vision: {}: {}", session_path, e);
+            exit(1);
+        });
+
+        let mut editor = session.restore().unwrap_or_else(|e| {
+            eprintln!("vision: {}", e);
+            exit(1);
+        });
+        editor.listen();
+        return;
+    }
+
     let buffer = match args.path_str {
         Some(path_str) => {
             let path = Path::new(&path_str);
+
+            // Saving would try to write over the directory, so don't get that far
+            if path.is_dir() {
+                eprintln!("vision: {} is a directory", path_str);
+                exit(1);
+            }
+
             match path.is_file() {
-                true => path_str
-                    .parse::<Buffer>()
-                    .expect("Could not open file. Path is invalid."),
+                true => path_str.parse::<Buffer>().unwrap_or_else(|e| {
+                    eprintln!("vision: cannot open {}: {}", path_str, e);
+                    exit(1);
+                }),
                 false => Buffer::new(Some(path_str)),
             }
         }
+        // Piped input, like `cat file | vision`, is read into an unnamed buffer
+        None if !stdin().is_terminal() => Buffer::from_reader(None, stdin().lock()).unwrap_or_else(|e| {
+            eprintln!("vision: cannot read from stdin: {}", e);
+            exit(1);
+        }),
         None => Buffer::new(None),
     };
 
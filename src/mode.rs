@@ -1,14 +1,16 @@
-use std::{io::{stdout, Write}, thread, time::Duration};
+use std::{cmp, fmt::Debug, io::{stdout, Write}};
 
-use crossterm::{cursor::{position, MoveDown, MoveLeft, MoveRight, MoveTo, MoveToNextLine, MoveToPreviousLine, MoveUp}, event::{read, Event, KeyCode, KeyModifiers}, execute, style::Color, terminal::{enable_raw_mode, Clear, ClearType}};
+use crossterm::{cursor::{MoveDown, MoveLeft, MoveToPreviousLine}, event::{read, Event, KeyCode, KeyEvent, KeyModifiers, MouseButton, MouseEventKind}, execute, style::Color, terminal::{enable_raw_mode, Clear, ClearType}};
 
-use crate::{print_bg, print_fg, utils, Action, Command, Cursor, CursorPosition, Direction, Editor, Redo, Undo};
+use crate::{print_bg, print_fg, utils, Action, Command, Cursor, CursorPosition, Direction, Editor, Redo, Region, RunError, Selection, SelectionKind, Undo};
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub enum Mode {
     Normal,
     Insert,
     Command,
+    Visual,
+    VisualLine,
 }
 
 impl Mode {
@@ -17,6 +19,8 @@ impl Mode {
             Self::Normal => Box::new(NormalMode),
             Self::Insert => Box::new(InsertMode),
             Self::Command => Box::new(CommandMode),
+            Self::Visual => Box::new(VisualMode(SelectionKind::Char)),
+            Self::VisualLine => Box::new(VisualMode(SelectionKind::Line)),
         }
     }
 }
@@ -26,9 +30,22 @@ pub trait ModeBehaviour {
     fn print(&self);
 }
 
+// Waits for a key press, skipping the mouse events that come in while the mouse is captured
+fn read_key() -> KeyEvent {
+    loop {
+        if let Event::Key(key_event) = read().unwrap() {
+            return key_event;
+        }
+    }
+}
+
+// What the terminal sends for Ctrl-w, kept pending until the key naming the window command
+const WINDOW_KEY: char = '\u{17}';
+
 pub struct NormalMode;
 pub struct InsertMode;
 pub struct CommandMode;
+pub struct VisualMode(pub SelectionKind);
 
 impl ModeBehaviour for NormalMode {
     fn print(&self) {
@@ -37,58 +54,795 @@ impl ModeBehaviour for NormalMode {
 
     fn listen(&mut self, editor: &mut Editor) {
         enable_raw_mode().unwrap();
-        while let Ok(event) = read() {
+
+        // First key of a two key command like gg, dropped if the next key doesn't complete it
+        let mut pending: Option<char> = None;
+
+        // Digits typed before a command, repeating it that many times
+        let mut count: Option<usize> = None;
+
+        while let Ok(event) = editor.read_event() {
             match event {
                 Event::Key(key_event) => {
-                    let normal_pos = position().unwrap();
+                    editor.message = None;
+
+                    // 0 only adds to a count already started, otherwise it moves to the line start.
+                    // Ctrl-6 is Ctrl-^, not part of a count.
+                    if let KeyCode::Char(c @ '0'..='9') = key_event.code
+                        && (c != '0' || count.is_some())
+                        && !key_event.modifiers.contains(KeyModifiers::CONTROL)
+                    {
+                        let digit = c.to_digit(10).unwrap() as usize;
+                        count = Some(count.unwrap_or(0).saturating_mul(10).saturating_add(digit));
+                        Self::show_pending(editor, count, pending);
+                        editor.set_mode(Mode::Normal);
+                        continue;
+                    }
+
+                    let prev = pending.take();
+                    let given = count.take();
+                    let times = given.unwrap_or(1);
+                    editor.pending_keys.clear();
+
+                    // Whatever this key changes is undone as one step
+                    editor.begin_change();
+
                     match key_event.code {
+                        // Ctrl-w starts a window command, named by the key after it
+                        KeyCode::Char(c) if prev == Some(WINDOW_KEY) => self.process_window_command(editor, c),
+                        KeyCode::Char('w') if key_event.modifiers.contains(KeyModifiers::CONTROL) => pending = Some(WINDOW_KEY),
+
+                        // Ctrl-^ goes back to the buffer before this one, or with a count to the
+                        // buffer with that number. Terminals send it as Ctrl-6.
+                        KeyCode::Char('^' | '6') if key_event.modifiers.contains(KeyModifiers::CONTROL) => self.process_buffer_jump(editor, given),
+
                         // Other mode listeners
                         KeyCode::Char(':') => CommandMode.listen(editor),
-                        KeyCode::Char('i') => InsertMode.listen(editor),
+                        KeyCode::Char('/') => CommandMode.search(editor),
+                        KeyCode::Char('i') if editor.check_modifiable() => InsertMode.listen(editor),
+                        KeyCode::Char('a') if editor.check_modifiable() => self.process_append(editor, false),
+                        KeyCode::Char('A') if editor.check_modifiable() => self.process_append(editor, true),
+                        KeyCode::Char('I') if editor.check_modifiable() => self.process_insert_first_non_blank(editor),
+                        KeyCode::Char('o') if editor.check_modifiable() => self.process_open_line(editor, true),
+                        KeyCode::Char('O') if editor.check_modifiable() => self.process_open_line(editor, false),
+                        KeyCode::Char('v') if prev == Some('g') => {
+                            if let Some(selection) = editor.last_visual {
+                                VisualMode(selection.kind).select(editor, selection);
+                            }
+                        }
+                        KeyCode::Char('v') => VisualMode(SelectionKind::Char).listen(editor),
+                        KeyCode::Char('V') => VisualMode(SelectionKind::Line).listen(editor),
+
 
-                        // Cursor movement
-                        KeyCode::Char('h') => {
+                        // Cursor movement. gj and gk go past every row a wrapped line takes up, where j
+                        // and k step through them.
+                        KeyCode::Char(c @ ('j' | 'k')) if prev == Some('g') => self.repeat_motion(editor, times, |editor| {
+                            editor.move_logical_line(c == 'k');
+                        }),
+                        KeyCode::Char('h') => self.repeat_motion(editor, times, |editor| {
                             editor.move_cursor(Direction::Left);
-                        }
-                        KeyCode::Char('k') => {
+                        }),
+                        KeyCode::Char('k') => self.repeat_motion(editor, times, |editor| {
                             editor.move_cursor(Direction::Up);
-                        }
-                        KeyCode::Char('l') => {
+                        }),
+                        KeyCode::Char('l') => self.repeat_motion(editor, times, |editor| {
                             editor.move_cursor(Direction::Right);
-                        }
-                        KeyCode::Char('j') => {
+                        }),
+                        KeyCode::Char('j') => self.repeat_motion(editor, times, |editor| {
                             editor.move_cursor(Direction::Down);
+                        }),
+                        KeyCode::Char('w') => self.repeat_motion(editor, times, Editor::move_word_forward),
+                        KeyCode::Char('b') => self.repeat_motion(editor, times, Editor::move_word_back),
+                        KeyCode::Char('e') => self.repeat_motion(editor, times, Editor::move_word_end),
+                        KeyCode::Char('0') => editor.move_line_start(),
+                        KeyCode::Char('^') => editor.move_first_non_blank(),
+                        KeyCode::Char('$') => editor.move_line_end(),
+                        KeyCode::Char('%') => editor.move_matching_bracket(),
+
+                        // With a count these go to that line instead of the first or last
+                        KeyCode::Char('g') if prev == Some('g') => self.process_goto_line(editor, given.unwrap_or(1)),
+                        KeyCode::Char('G') => self.process_goto_line(editor, given.unwrap_or(editor.file.length())),
+
+                        // "+ and "* pick the system clipboard for the command typed after them
+                        KeyCode::Char(c @ ('+' | '*')) if prev == Some('"') => {
+                            editor.clipboard_register = true;
+                            pending = Some(c);
+                            count = given;
+                        }
+
+                        // The count is kept for the key that completes the command
+                        KeyCode::Char(c @ ('g' | 'd' | 'y' | '"')) if prev != Some(c) => {
+                            pending = Some(c);
+                            count = given;
+                        }
+                        KeyCode::Char('d') if editor.check_modifiable() => {
+                            // Never more lines than there are from the cursor down
+                            let (row, _) = editor.cursor_at();
+                            let lines = cmp::min(times, editor.file.length() - row);
+                            for _ in 0..lines {
+                                self.process_delete_line(editor);
+                            }
+                            editor.report_lines(lines, format!("{} fewer lines", lines));
+                        }
+                        KeyCode::Char('y') => self.process_yank_line(editor),
+                        KeyCode::Char('p') if editor.check_modifiable() => self.process_put(editor, true),
+                        KeyCode::Char('P') if editor.check_modifiable() => self.process_put(editor, false),
+                        KeyCode::Char('x') if editor.check_modifiable() && !editor.file.is_empty() => {
+                            // Never more chars than there are from the cursor to the line end
+                            let (row, col) = editor.cursor_at();
+                            let len = editor.file.get_line_at(row).map_or(0, |line| line.len());
+                            for _ in 0..cmp::min(times, len.saturating_sub(col)) {
+                                self.process_delete_char(editor);
+                            }
+                        }
+                        KeyCode::Char(c @ ('n' | 'N')) => {
+                            for _ in 0..cmp::min(times, editor.file.length()) {
+                                if let Err(e) = editor.search_next(c == 'n') {
+                                    CommandMode.report(editor, e);
+                                    break;
+                                }
+                            }
                         }
 
                         // Shortcuts comming soon
-                        KeyCode::Char('u') => {
+                        KeyCode::Char('u') if editor.check_modifiable() => {
+                            let groups = editor.file.history_groups();
                             editor.undo();
+                            editor.report_undo(groups, Action::Undo);
                             editor.render();
                         },
 
-                        KeyCode::Char('r') => {
-                            if key_event.modifiers.contains(KeyModifiers::CONTROL) {
-                                editor.redo();
+                        KeyCode::Char('U') if editor.check_modifiable() => {
+                            let pos = editor.cursor_pos();
+                            let before = editor.cursor_at();
+                            if editor.file.undo_line() {
+                                // The restored line may be shorter than where the cursor was
+                                let len = editor.file.get_line(pos.1 as usize).map_or(0, |line| line.len());
+                                editor.place_cursor((cmp::min(pos.0, len), pos.1));
+
+                                editor.cursor.history.update(CursorPosition::new(before, editor.cursor_at()), Action::Do);
                                 editor.render();
                             }
                         },
+
+                        KeyCode::Char('r') if key_event.modifiers.contains(KeyModifiers::CONTROL) && editor.check_modifiable() => {
+                            let groups = editor.file.history_groups();
+                            editor.redo();
+                            editor.report_undo(groups, Action::Redo);
+                            editor.render();
+                        },
                         _ => {}
                     }
 
+                    // The register picked with " lasts until the command after it is done
+                    if pending.is_none() {
+                        editor.clipboard_register = false;
+                    }
+
+                    // Redraws whatever depends on where the cursor ended up, like the matching bracket
+                    Self::show_pending(editor, count, pending);
                     editor.set_mode(Mode::Normal);
+                    editor.render();
+                }
+                Event::Mouse(mouse_event) => {
+                    match mouse_event.kind {
+                        MouseEventKind::Down(MouseButton::Left) => editor.click(mouse_event.column, mouse_event.row),
+                        MouseEventKind::ScrollDown => editor.scroll(true),
+                        MouseEventKind::ScrollUp => editor.scroll(false),
+                        _ => continue,
+                    }
+                    editor.message = None;
+                    editor.set_mode(Mode::Normal);
+                    editor.render();
+                }
+                Event::Resize(_, _) => {
+                    editor.redraw();
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+impl NormalMode {
+    // What's been typed of a command that isn't finished, for showcmd
+    fn show_pending(editor: &mut Editor, count: Option<usize>, pending: Option<char>) {
+        let count = count.map_or(String::new(), |count| count.to_string());
+        let key = match pending {
+            Some(WINDOW_KEY) => "^W".to_string(),
+            Some(c @ ('+' | '*')) => format!("\"{}", c),
+            Some(c) => c.to_string(),
+            None => String::new(),
+        };
+        editor.pending_keys = count + &key;
+    }
+
+    // Moves up to `times` times, stopping early once the cursor can't go any further
+    fn repeat_motion(&mut self, editor: &mut Editor, times: usize, mut motion: impl FnMut(&mut Editor)) {
+        for _ in 0..times {
+            let before = (editor.cursor_at(), editor.file.start);
+            motion(editor);
+            if (editor.cursor_at(), editor.file.start) == before {
+                break;
+            }
+        }
+    }
+
+    fn process_buffer_jump(&mut self, editor: &mut Editor, number: Option<usize>) {
+        let index = match number {
+            Some(number) if (1..=editor.buffer_count()).contains(&number) => number - 1,
+            Some(_) => return CommandMode.report(editor, RunError::UnknownBuffer),
+            None => match editor.alternate_buffer() {
+                Some(alternate) => alternate,
+                None => return CommandMode.report(editor, RunError::NoAlternateBuffer),
+            },
+        };
+        editor.switch_buffer(index);
+    }
+
+    // The key after Ctrl-w: s splits, c closes the window the cursor is in and o the other one,
+    // w goes to the other window and j and k to the one below or above. Ctrl can be kept held,
+    // like Ctrl-w Ctrl-w, since only the char is looked at.
+    fn process_window_command(&mut self, editor: &mut Editor, c: char) {
+        match c {
+            's' if !editor.split_window() => CommandMode.report(editor, RunError::AlreadySplit),
+            'c' if !editor.close_window() => CommandMode.report(editor, RunError::LastWindow),
+            'o' if !editor.only_window() => editor.message = Some("Already only one window".to_string()),
+            'w' => editor.switch_window(),
+            'j' if utils::region() == Region::Top => editor.switch_window(),
+            'k' if utils::region() == Region::Bottom => editor.switch_window(),
+            _ => {}
+        }
+    }
+
+    // Goes to a 1-based line, clamped to the buffer, keeping the cursor column
+    fn process_goto_line(&mut self, editor: &mut Editor, line: usize) {
+        let row = line.clamp(1, editor.file.length()) - 1;
+        editor.jump_to(row, editor.cursor_pos().0);
+    }
 
-                    // Reposition the cursor if it came out of command mode
-                    if key_event.code == KeyCode::Char(':') {
-                        execute!(stdout(), MoveTo(normal_pos.0, normal_pos.1)).unwrap();
+    fn process_delete_line(&mut self, editor: &mut Editor) {
+        // Emptying an empty buffer again would only leave a change behind to undo
+        if editor.file.is_empty() {
+            return;
+        }
+
+        let (col, row) = editor.cursor_pos();
+        let abs_row = editor.file.start + row as usize;
+        let before = (abs_row, col);
+
+        // A buffer always keeps one line, so the last one left is emptied instead
+        if editor.file.length() == 1 {
+            editor.file.set_line(row as usize, vec![], Action::Do);
+        } else {
+            editor.file.delete_line(row as usize, Action::Do);
+        }
+
+        // Land on the line that took its place, or the one above if the last line went
+        let target = cmp::min(abs_row, editor.file.length() - 1);
+        let target_col = editor.file.get_line_at(target).map_or(0, |line| utils::first_non_blank(line));
+        editor.jump_to(target, target_col);
+
+        editor.cursor.history.update(CursorPosition::new(before, editor.cursor_at()), Action::Do);
+        editor.render();
+    }
+
+    fn process_delete_char(&mut self, editor: &mut Editor) {
+        let (col, row) = editor.cursor_pos();
+        let before = (editor.file.start + row as usize, col);
+        let len = editor.file.get_line(row as usize).map_or(0, |line| line.len());
+        if col >= len {
+            return;
+        }
+
+        editor.file.delete_char(row as usize, col, Action::Do);
+
+        // Deleting the last char leaves the cursor past the end, so pull it back onto the line
+        editor.place_cursor((cmp::min(col, (len - 1).saturating_sub(1)), row));
+
+        editor.cursor.history.update(CursorPosition::new(before, editor.cursor_at()), Action::Do);
+        editor.render();
+    }
+
+    fn process_yank_line(&mut self, editor: &mut Editor) {
+        let row = editor.cursor_pos().1 as usize;
+        if let Some(line) = editor.file.get_line(row) {
+            editor.yank(vec![line.clone()], true);
+        }
+    }
+
+    // Puts the register's lines below the cursor line, or above it when `below` is false
+    fn process_put(&mut self, editor: &mut Editor, below: bool) {
+        editor.paste_clipboard();
+        if editor.register().is_empty() {
+            return;
+        }
+
+        let (col, row) = editor.cursor_pos();
+        let before = (editor.file.start + row as usize, col);
+        if !editor.register_linewise() {
+            self.process_put_text(editor, below);
+            editor.cursor.history.update(CursorPosition::new(before, editor.cursor_at()), Action::Do);
+            editor.render();
+            return;
+        }
+
+        let first = editor.file.start + row as usize + below as usize;
+        let lines = editor.register().to_vec();
+        let target_col = utils::first_non_blank(&lines[0]);
+
+        for (i, line) in lines.into_iter().enumerate() {
+            editor.file.insert_line(first - editor.file.start + i, line, Action::Do);
+        }
+        editor.jump_to(first, target_col);
+
+        editor.cursor.history.update(CursorPosition::new(before, editor.cursor_at()), Action::Do);
+        editor.render();
+    }
+
+    // Starts inserting after the cursor, or at the end of the line when `end` is true
+    fn process_append(&mut self, editor: &mut Editor, end: bool) {
+        let (col, row) = editor.cursor_pos();
+        let len = editor.file.get_line(row as usize).map_or(0, |line| line.len());
+
+        // Never past the end of the line, even if the cursor already sits there
+        let target = match end {
+            true => len,
+            false => cmp::min(col + 1, len),
+        };
+        editor.place_cursor((target, row));
+        InsertMode.listen(editor);
+    }
+
+    // Starts inserting before the first non-blank char, or at the start of a line that's all blank
+    fn process_insert_first_non_blank(&mut self, editor: &mut Editor) {
+        let row = editor.cursor_pos().1;
+        let line = editor.file.get_line(row as usize).cloned().unwrap_or_default();
+
+        let col = match utils::first_non_blank(&line) {
+            col if col == line.len() => 0,
+            col => col,
+        };
+        editor.place_cursor((col, row));
+        InsertMode.listen(editor);
+    }
+
+    // Opens a new line below the cursor line, or above it when `below` is false, and starts
+    // inserting on it with the same indentation as the cursor line
+    fn process_open_line(&mut self, editor: &mut Editor, below: bool) {
+        let (col, row) = editor.cursor_pos();
+        let before = (editor.file.start + row as usize, col);
+        let line = editor.file.get_line(row as usize).cloned().unwrap_or_default();
+
+        let mut indentation = InsertMode::leading_whitespace(&line);
+        if below && editor.settings.smartindent && InsertMode::opens_block(&line) {
+            indentation.extend(editor.settings.indent());
+        }
+
+        let new_row = row as usize + below as usize;
+        let len = indentation.len();
+        editor.file.insert_line(new_row, indentation, Action::Do);
+        editor.jump_to(editor.file.start + new_row, len);
+
+        editor.cursor.history.update(CursorPosition::new(before, editor.cursor_at()), Action::Do);
+        editor.render();
+        InsertMode.listen(editor);
+    }
+
+    // Puts text yanked from within lines after the cursor, or before it when `below` is false
+    fn process_put_text(&mut self, editor: &mut Editor, below: bool) {
+        let (row, col) = editor.cursor_at();
+        let len = editor.file.get_line_at(row).map_or(0, |line| line.len());
+        let at = cmp::min(col + (below && len > 0) as usize, len);
+
+        let lines = editor.register().to_vec();
+        editor.file.insert_text((row, at), lines.clone());
+
+        // Land on the last char that was put in, like vim
+        let last = lines.last().map_or(0, |line| line.len());
+        let end = match lines.len() {
+            0 | 1 => (row, (at + last).saturating_sub(1)),
+            n => (row + n - 1, last.saturating_sub(1)),
+        };
+        editor.jump_to(end.0, end.1);
+    }
+}
+
+impl ModeBehaviour for VisualMode {
+    fn print(&self) {
+        match self.0 {
+            SelectionKind::Char => print_fg!(Color::Blue, "--VISUAL MODE--"),
+            SelectionKind::Line => print_fg!(Color::Blue, "--VISUAL LINE--"),
+        }
+    }
+
+    fn listen(&mut self, editor: &mut Editor) {
+        let selection = Selection::new(editor.cursor_at(), self.0);
+        self.select(editor, selection);
+    }
+}
+
+impl VisualMode {
+    // Starts visual mode with `selection` already made, the cursor going to its head
+    pub fn select(&mut self, editor: &mut Editor, mut selection: Selection) {
+        // Lines may have gone since it was made, like when gv brings back an old one
+        let last = editor.file.length() - 1;
+        selection.anchor.0 = cmp::min(selection.anchor.0, last);
+        editor.jump_to(selection.head.0, selection.head.1);
+        selection.head = editor.cursor_at();
+
+        editor.selection = Some(selection);
+        self.set_mode(editor);
+        editor.render();
+
+        // : leaves visual mode first, so the command runs on the marks it sets
+        let mut command = false;
+
+        // Whether " was the last key, to pick a register with the next one
+        let mut quote = false;
+
+        while let Ok(event) = editor.read_event() {
+            let Some(key_event) = event.as_key_event() else {
+                continue;
+            };
+
+            let before = editor.selection;
+            let quoted = std::mem::take(&mut quote);
+            match key_event.code {
+                KeyCode::Esc => break,
+                KeyCode::Char(':') => {
+                    command = true;
+                    break;
+                }
+
+                // Pressing the key for the other kind switches to it, pressing the same one leaves
+                KeyCode::Char(c @ ('v' | 'V')) => {
+                    let kind = if c == 'v' { SelectionKind::Char } else { SelectionKind::Line };
+                    if kind == self.0 {
+                        break;
                     }
 
-                    enable_raw_mode().unwrap();
+                    self.0 = kind;
+                    if let Some(selection) = editor.selection.as_mut() {
+                        selection.kind = kind;
+                    }
+                    self.set_mode(editor);
                 }
-                Event::Resize(col, row) => {
-                    editor.render();
+
+                KeyCode::Char('h') => {
+                    editor.move_cursor(Direction::Left);
+                }
+                KeyCode::Char('k') => {
+                    editor.move_cursor(Direction::Up);
+                }
+                KeyCode::Char('l') => {
+                    editor.move_cursor(Direction::Right);
+                }
+                KeyCode::Char('j') => {
+                    editor.move_cursor(Direction::Down);
+                }
+                KeyCode::Char('w') => editor.move_word_forward(),
+                KeyCode::Char('b') => editor.move_word_back(),
+                KeyCode::Char('e') => editor.move_word_end(),
+                KeyCode::Char('0') => editor.move_line_start(),
+                KeyCode::Char('^') => editor.move_first_non_blank(),
+                KeyCode::Char('$') => editor.move_line_end(),
+                KeyCode::Char('%') => editor.move_matching_bracket(),
+                KeyCode::Char('G') => editor.jump_to(editor.file.length() - 1, editor.cursor_pos().0),
+
+                // "+ and "* have the operator after them yank to or put from the system clipboard
+                KeyCode::Char('"') => quote = true,
+                KeyCode::Char('+' | '*') if quoted => editor.clipboard_register = true,
+
+                // Operators act on the selection and leave visual mode
+                KeyCode::Char('d' | 'x') => {
+                    if editor.check_modifiable() {
+                        self.process_delete(editor);
+                    }
+                    break;
+                }
+                KeyCode::Char('y') => {
+                    self.process_yank(editor);
+                    break;
+                }
+                KeyCode::Char('p') => {
+                    if editor.check_modifiable() {
+                        self.process_put(editor);
+                    }
+                    break;
+                }
+                KeyCode::Char(c @ ('u' | 'U' | '~')) => {
+                    if editor.check_modifiable() {
+                        self.process_case(editor, c);
+                    }
+                    break;
+                }
+                KeyCode::Char(c @ ('>' | '<')) => {
+                    if editor.check_modifiable() {
+                        self.process_shift(editor, c == '>');
+                    }
+                    break;
                 }
                 _ => {}
             }
+
+            let head = editor.cursor_at();
+            if let Some(selection) = editor.selection.as_mut() {
+                selection.head = head;
+            }
+            self.mark_changed(editor, before);
+            editor.render();
+        }
+
+        let before = editor.selection.take();
+        editor.last_visual = before;
+        editor.clipboard_register = false;
+        self.mark_changed(editor, before);
+        editor.set_mode(Mode::Normal);
+        editor.render();
+
+        if command {
+            CommandMode.prefilled(editor, "'<,'>");
+        }
+    }
+
+    fn set_mode(&self, editor: &mut Editor) {
+        match self.0 {
+            SelectionKind::Char => editor.set_mode(Mode::Visual),
+            SelectionKind::Line => editor.set_mode(Mode::VisualLine),
+        }
+    }
+
+    // The selection's bounds as a start and an end just past its last char
+    fn span(&self, editor: &Editor) -> Option<((usize, usize), (usize, usize))> {
+        let (start, end) = editor.selection?.bounds();
+        let len = editor.file.get_line_at(end.0).map_or(0, |line| line.len());
+        Some((start, (end.0, cmp::min(end.1 + 1, len))))
+    }
+
+    fn process_yank(&mut self, editor: &mut Editor) {
+        if self.0 == SelectionKind::Line {
+            return self.process_yank_lines(editor);
+        }
+        let Some((start, end)) = self.span(editor) else {
+            return;
+        };
+
+        let text = editor.file.text(start, end);
+        editor.yank(text, false);
+        editor.jump_to(start.0, start.1);
+    }
+
+    fn process_delete(&mut self, editor: &mut Editor) {
+        if self.0 == SelectionKind::Line {
+            return self.process_delete_lines(editor);
+        }
+        let Some((start, end)) = self.span(editor) else {
+            return;
+        };
+        let before = editor.cursor_at();
+
+        // Deleted text can be put back with p, like vim
+        let text = editor.file.text(start, end);
+        editor.yank(text, false);
+
+        editor.file.delete_text(start, end);
+        editor.jump_to(start.0, start.1);
+        editor.cursor.history.update(CursorPosition::new(before, editor.cursor_at()), Action::Do);
+    }
+
+    // Puts the register in place of the selection, which goes into the register instead, so
+    // doing it again swaps them back. Line-wise text put into a char-wise selection goes in on
+    // lines of its own, and char-wise text replacing whole lines gets lines of its own too.
+    fn process_put(&mut self, editor: &mut Editor) {
+        let Some(selection) = editor.selection else {
+            return;
+        };
+        editor.paste_clipboard();
+        if editor.register().is_empty() {
+            return;
+        }
+        let before = editor.cursor_at();
+        let register = editor.register().to_vec();
+        let linewise = editor.register_linewise();
+
+        // Both the delete and the put are taken back by the same undo
+        editor.begin_change();
+
+        if self.0 == SelectionKind::Line {
+            let rows = selection.rows();
+            let start = *rows.start();
+            let lines = rows.clone().filter_map(|row| editor.file.get_line_at(row).cloned()).collect();
+
+            // Deleting every line still leaves one behind, which the first put line replaces
+            let whole = rows.clone().count() == editor.file.length();
+            for _ in rows {
+                if editor.file.length() == 1 {
+                    editor.file.set_line_at(start, vec![], Action::Do);
+                } else {
+                    editor.file.delete_line_at(start, Action::Do);
+                }
+            }
+            for (i, line) in register.into_iter().enumerate() {
+                match whole && i == 0 {
+                    true => editor.file.set_line_at(start, line, Action::Do),
+                    false => editor.file.insert_line_at(start + i, line, Action::Do),
+                }
+            }
+
+            editor.yank(lines, true);
+            let col = editor.file.get_line_at(start).map_or(0, |line| utils::first_non_blank(line));
+            editor.jump_to(start, col);
+        } else {
+            let Some((start, end)) = self.span(editor) else {
+                return;
+            };
+            let text = editor.file.text(start, end);
+            editor.file.delete_text(start, end);
+
+            // Land on the first put line, or on the last char put like p does
+            let target = match linewise {
+                true => {
+                    let mut lines = vec![vec![]];
+                    lines.extend(register);
+                    lines.push(vec![]);
+                    editor.file.insert_text(start, lines);
+
+                    let col = editor.file.get_line_at(start.0 + 1).map_or(0, |line| utils::first_non_blank(line));
+                    (start.0 + 1, col)
+                }
+                false => {
+                    let last = register.last().map_or(0, |line| line.len());
+                    let target = match register.len() {
+                        0 | 1 => (start.0, (start.1 + last).saturating_sub(1)),
+                        n => (start.0 + n - 1, last.saturating_sub(1)),
+                    };
+                    editor.file.insert_text(start, register);
+                    target
+                }
+            };
+
+            editor.yank(text, false);
+            editor.jump_to(target.0, target.1);
+        }
+
+        editor.cursor.history.update(CursorPosition::new(before, editor.cursor_at()), Action::Do);
+    }
+
+    // Lowercases the selection for u, uppercases it for U or swaps the case of every char for ~,
+    // leaving the cursor at its start. A char can change into more than one, like ß into SS.
+    fn process_case(&mut self, editor: &mut Editor, key: char) {
+        let Some(selection) = editor.selection else {
+            return;
+        };
+        let before = editor.cursor_at();
+        let change = |c: char| -> Vec<char> {
+            match key {
+                'u' => c.to_lowercase().collect(),
+                'U' => c.to_uppercase().collect(),
+                _ if c.is_uppercase() => c.to_lowercase().collect(),
+                _ => c.to_uppercase().collect(),
+            }
+        };
+
+        editor.begin_change();
+        for row in selection.rows() {
+            let Some(line) = editor.file.get_line_at(row) else {
+                continue;
+            };
+            let Some(cols) = selection.cols(row, line.len()) else {
+                continue;
+            };
+            let cols = cmp::min(cols.start, line.len())..cmp::min(cols.end, line.len());
+
+            let mut changed = line[..cols.start].to_vec();
+            changed.extend(line[cols.clone()].iter().flat_map(|c| change(*c)));
+            changed.extend(&line[cols.end..]);
+            if changed != *line {
+                editor.file.set_line_at(row, changed, Action::Do);
+            }
+        }
+
+        let (start, _) = selection.bounds();
+        let col = if self.0 == SelectionKind::Line { 0 } else { start.1 };
+        editor.jump_to(start.0, col);
+        editor.cursor.history.update(CursorPosition::new(before, editor.cursor_at()), Action::Do);
+    }
+
+    fn process_yank_lines(&mut self, editor: &mut Editor) {
+        let Some(selection) = editor.selection else {
+            return;
+        };
+
+        let lines = selection.rows().filter_map(|row| editor.file.get_line_at(row).cloned()).collect();
+        editor.yank(lines, true);
+
+        let start = *selection.rows().start();
+        let col = editor.cursor_pos().0;
+        editor.jump_to(start, col);
+    }
+
+    fn process_delete_lines(&mut self, editor: &mut Editor) {
+        let Some(selection) = editor.selection else {
+            return;
+        };
+        if editor.file.is_empty() {
+            return;
+        }
+        let before = editor.cursor_at();
+        let rows = selection.rows();
+        let start = *rows.start();
+
+        let lines = rows.clone().filter_map(|row| editor.file.get_line_at(row).cloned()).collect();
+        editor.yank(lines, true);
+
+        // Moving around in visual mode isn't an edit, so the deletion is still its own undo step
+        editor.begin_change();
+        let count = rows.clone().count();
+        for _ in rows {
+            // A buffer always keeps one line, so the last one left is emptied instead
+            if editor.file.length() == 1 {
+                editor.file.set_line_at(start, vec![], Action::Do);
+            } else {
+                editor.file.delete_line_at(start, Action::Do);
+            }
+        }
+
+        // Land on the line that took their place, or the one above if they reached the end
+        let target = cmp::min(start, editor.file.length() - 1);
+        let target_col = editor.file.get_line_at(target).map_or(0, |line| utils::first_non_blank(line));
+        editor.jump_to(target, target_col);
+        editor.report_lines(count, format!("{} fewer lines", count));
+
+        editor.cursor.history.update(CursorPosition::new(before, editor.cursor_at()), Action::Do);
+    }
+
+    // Indents every selected line by one level, or outdents by as much of a level as each one has
+    fn process_shift(&mut self, editor: &mut Editor, indent: bool) {
+        let Some(selection) = editor.selection else {
+            return;
+        };
+        let before = editor.cursor_at();
+        let rows = selection.rows();
+        let start = *rows.start();
+
+        editor.begin_change();
+        for row in rows {
+            let Some(line) = editor.file.get_line_at(row) else {
+                break;
+            };
+
+            // Empty lines are left alone so no trailing whitespace is made
+            let shifted = if indent {
+                if line.is_empty() {
+                    continue;
+                }
+                let mut shifted = editor.settings.indent();
+                shifted.extend(line);
+                shifted
+            } else {
+                let leading = InsertMode::leading_whitespace(line).len();
+                let removed = InsertMode::level_before(line, leading, editor.settings.shiftwidth);
+                if removed == 0 {
+                    continue;
+                }
+                let mut shifted = line[..leading - removed].to_vec();
+                shifted.extend(&line[leading..]);
+                shifted
+            };
+            editor.file.set_line_at(row, shifted, Action::Do);
+        }
+
+        let col = editor.file.get_line_at(start).map_or(0, |line| utils::first_non_blank(line));
+        editor.jump_to(start, col);
+        editor.cursor.history.update(CursorPosition::new(before, editor.cursor_at()), Action::Do);
+    }
+
+    // Rows whose highlight changed since `before` need drawing again
+    fn mark_changed(&self, editor: &mut Editor, before: Option<Selection>) {
+        let rows = [before, editor.selection].into_iter().flatten().flat_map(|selection| selection.rows());
+        for row in rows.collect::<Vec<_>>() {
+            editor.file.mark_dirty(row);
         }
     }
 }
@@ -99,16 +853,22 @@ impl ModeBehaviour for InsertMode {
     }
 
     fn listen(&mut self, editor: &mut Editor) {
+        // Everything typed until leaving insert mode is undone as one step with the normal mode key
+        // that started it, which already began the change, so `o` is undone along with the typing
         editor.set_mode(Mode::Insert);
 
-        while let Ok(event) = read() {
+        while let Ok(event) = editor.read_event() {
             if let Some(key_event) = event.as_key_event() {
-                let (col, row) = position().unwrap();
+                let (col, row) = editor.cursor_pos();
                 match key_event.code {
                     KeyCode::Esc => break,
                     KeyCode::Tab => self.process_tab(editor, col, row),
+                    KeyCode::BackTab => self.process_backtab(editor, col, row),
                     KeyCode::Enter => self.process_enter(editor, col, row),
                     KeyCode::Backspace => self.process_backspace(editor, col, row),
+                    KeyCode::Delete => self.process_delete(editor, col, row),
+                    KeyCode::Char('a') if key_event.modifiers.contains(KeyModifiers::CONTROL) => self.process_line_start(editor, row),
+                    KeyCode::Char('e') if key_event.modifiers.contains(KeyModifiers::CONTROL) => self.process_line_end(editor, row),
                     KeyCode::Char(c) => self.process_char(editor, col, row, c),
                     _ => {}
                 }
@@ -119,48 +879,62 @@ impl ModeBehaviour for InsertMode {
 }
 
 impl InsertMode {
-    fn process_tab(&mut self, editor: &mut Editor, col: u16, row: u16) {
-        for i in 0..4 {
-            if (position().unwrap().0) % 4 == 0 && i != 0 {
-                break;
-            }
-            self.process_char(editor, col, row, ' ');
+    fn process_line_start(&mut self, editor: &mut Editor, row: u16) {
+        editor.place_cursor((0, row));
+    }
+
+    fn process_line_end(&mut self, editor: &mut Editor, row: u16) {
+        if let Some(line) = editor.file.get_line(row as usize) {
+            editor.place_cursor((line.len(), row));
+        }
+    }
+
+    fn process_tab(&mut self, editor: &mut Editor, col: usize, row: u16) {
+        if !editor.settings.expandtab {
+            self.process_char(editor, col, row, '\t');
+            return;
+        }
+
+        // Spaces up to the next tab stop
+        let tabstop = editor.settings.tabstop;
+        let line = editor.file.get_line(row as usize).expect("Buffer has no starting line");
+        let visual = utils::visual_col(line, col, tabstop);
+        for i in 0..tabstop - visual % tabstop {
+            self.process_char(editor, col + i, row, ' ');
         }
     }
 
-    fn process_enter(&mut self, editor: &mut Editor, col: u16, row: u16) {
+    fn process_enter(&mut self, editor: &mut Editor, col: usize, row: u16) {
+        let before = (editor.file.start + row as usize, col);
         let line = editor.file.get_line(row as usize).expect("Failed to get line");
 
-        let first_half = (&line[0..col as usize]).to_vec();
-        let mut second_half = (&line[col as usize..line.len()]).to_vec();
+        let first_half = line[0..col].to_vec();
+        let mut second_half = line[col..line.len()].to_vec();
 
         // Indentation from previous line
-        let mut indentation = Vec::new();
-        for char in &first_half {
-            if *char == ' ' {
-                indentation.push(' ');
-            } else {
-                break;
-            }
-        }
+        let mut indentation = Self::leading_whitespace(&first_half);
         
         // Replace the current line with everything left of the cursor
         editor.file.set_line(row as usize, first_half.clone(), Action::Do);
-        execute!(stdout(), MoveToNextLine(1)).unwrap();
+        editor.place_cursor((0, row + 1));
         let mut new_row = row + 1;
         
         // // Insert another new line if user presses enter betweeen braces
         let left_char = first_half.last().copied();
         let right_char = second_half.first().copied();
         
-        if let (Some(left), Some(right)) = (left_char, right_char) {
-            if utils::braces(left, right) {
-                let mut new_line = indentation.clone();
-                new_line.extend(vec![' ', ' ', ' ', ' ']);
-                editor.file.insert_line(new_row as usize, new_line, Action::Do);
-                execute!(stdout(), MoveRight(4)).unwrap();
-                new_row += 1;
-            }
+        let step = editor.settings.indent();
+        if let (Some(left), Some(right)) = (left_char, right_char)
+            && utils::braces(left, right)
+        {
+            let mut new_line = indentation.clone();
+            new_line.extend(&step);
+            editor.file.insert_line(new_row as usize, new_line, Action::Do);
+            editor.shift_cursor(step.len() as isize);
+            new_row += 1;
+        } else if editor.settings.smartindent && Self::opens_block(&first_half) {
+            // Indent one level deeper after a line that ends with an opening brace
+            indentation.extend(step);
         }
 
         // Indent the second half
@@ -169,87 +943,166 @@ impl InsertMode {
         // Insert second half
         editor.file.insert_line(new_row as usize, second_half, Action::Do);
 
-        if indentation.len() > 0 {
-            execute!(stdout(), MoveRight(indentation.len() as u16)).unwrap();
+        if !indentation.is_empty() {
+            editor.shift_cursor(indentation.len() as isize);
         }
 
-        editor.cursor.history.update(CursorPosition::new((col, row), Cursor::pos()), Action::Do);
+        editor.cursor.history.update(CursorPosition::new(before, editor.cursor_at()), Action::Do);
     }
 
-    fn process_backspace(&mut self, editor: &mut Editor, col: u16, row: u16) {
-        // Don't do anything if user tries to delete the first column of the first row
-        if row == 0 && col == 0 {
+    // Takes one level of indentation off the line, as long as the cursor is still within it
+    fn process_backtab(&mut self, editor: &mut Editor, col: usize, row: u16) {
+        let before = (editor.file.start + row as usize, col);
+        let Some(line) = editor.file.get_line(row as usize) else {
+            return;
+        };
+
+        let indent = Self::leading_whitespace(line).len();
+        let removed = Self::level_before(line, indent, editor.settings.shiftwidth);
+        if col > indent || removed == 0 {
+            return;
+        }
+
+        let mut dedented = line[..indent - removed].to_vec();
+        dedented.extend(&line[indent..]);
+        editor.file.set_line(row as usize, dedented, Action::Do);
+        editor.place_cursor((col.saturating_sub(removed), row));
+        editor.cursor.history.update(CursorPosition::new(before, editor.cursor_at()), Action::Do);
+    }
+
+    // How many of the chars before `col` make up one level of indentation: a single tab, or up to
+    // shiftwidth spaces
+    fn level_before(line: &[char], col: usize, shiftwidth: usize) -> usize {
+        match line[..col].last() {
+            Some('\t') => 1,
+            _ => line[..col].iter().rev().take_while(|c| **c == ' ').take(shiftwidth).count(),
+        }
+    }
+
+    fn leading_whitespace(line: &[char]) -> Vec<char> {
+        line.iter().take_while(|c| **c == ' ' || **c == '\t').copied().collect()
+    }
+
+    // Whether the line ends with an opening brace, so whatever comes after it goes a level deeper
+    fn opens_block(line: &[char]) -> bool {
+        line.iter()
+            .rfind(|c| !c.is_whitespace())
+            .is_some_and(|c| utils::closeable(*c).is_some_and(|closing| utils::braces(*c, closing)))
+    }
+
+    fn process_backspace(&mut self, editor: &mut Editor, col: usize, row: u16) {
+        // Don't do anything if user tries to delete the first column of the first row. The top of
+        // the screen may not be the top of the file, so that goes by the absolute row.
+        let abs_row = editor.file.start + row as usize;
+        let before = (abs_row, col);
+        if abs_row == 0 && col == 0 {
             return;
         }
 
         // Delete a character if the cursor is NOT at the start of the line
         let line = editor.file.get_line(row as usize).expect("Buffer has no starting line");
         if col != 0 {
-            let left_char = line.get(col as usize - 1).copied();
-            let right_char = line.get(col as usize).copied();
+            let left_char = line.get(col - 1).copied();
+            let right_char = line.get(col).copied();
 
-            if let (Some(left), Some(right)) = (left_char, right_char) {
-                if utils::pair(left, right) {
-                    editor.file.delete_char(row as usize, col as usize, Action::Do);
-                }
+            if let (Some(left), Some(right)) = (left_char, right_char)
+                && utils::pair(left, right)
+            {
+                editor.file.delete_char(row as usize, col, Action::Do);
             }
 
-            editor.file.delete_char(row as usize, (col - 1) as usize, Action::Do);
-            execute!(stdout(), MoveLeft(1)).unwrap();
-            editor.cursor.history.update(CursorPosition::new((col, row), Cursor::pos()), Action::Do);
+            editor.file.delete_char(row as usize, col - 1, Action::Do);
+            editor.place_cursor((col - 1, row));
+            editor.cursor.history.update(CursorPosition::new(before, editor.cursor_at()), Action::Do);
             return;
         }
 
         // Otherwise, join the previous line with the next line (inverse operation of enter)
-        let mut prev_line = editor
-            .file
-            .get_line(row as usize - 1)
-            .expect("Failed to get prev_line")
-            .clone();
+        let line = line.clone();
+        let Some(mut prev_line) = editor.file.get_line_at(abs_row - 1).cloned() else {
+            return;
+        };
         let prev_line_len = prev_line.len();
         prev_line.extend(line);
-        editor.file.set_line(row as usize - 1, prev_line, Action::Do);
-        
-        execute!(stdout(), MoveUp(1)).unwrap();
-        
-        // Move the cursor to end of line if previous line isn't empty
-        if prev_line_len > 0 {
-            execute!(stdout(), MoveRight(prev_line_len as u16)).unwrap();
+        editor.file.set_line_at(abs_row - 1, prev_line, Action::Do);
+
+        // Delete current line and move the cursor to where the two lines meet, scrolling up if
+        // that's above the screen
+        editor.file.delete_line_at(abs_row, Action::Do);
+        editor.jump_to(abs_row - 1, prev_line_len);
+        editor.cursor.history.update(CursorPosition::new(before, editor.cursor_at()), Action::Do);
+    }
+
+    fn process_delete(&mut self, editor: &mut Editor, col: usize, row: u16) {
+        let before = (editor.file.start + row as usize, col);
+        let line_len = editor.file.get_line(row as usize).expect("Buffer has no starting line").len();
+
+        // Delete the character under the cursor if the cursor is NOT at the end of the line
+        if col < line_len {
+            editor.file.delete_char(row as usize, col, Action::Do);
+            editor.cursor.history.update(CursorPosition::new(before, editor.cursor_at()), Action::Do);
+            return;
         }
-        
-        // Delete current line and move cursor up
-        editor.file.delete_line(row as usize, Action::Do);
-        editor.cursor.history.update(CursorPosition::new((col, row), Cursor::pos()), Action::Do);
+
+        // Otherwise, join the next line onto the current line (inverse operation of enter)
+        let Some(next_line) = editor.file.get_line(row as usize + 1).cloned() else {
+            return;
+        };
+
+        let mut joined = editor.file.get_line(row as usize).expect("Buffer has no starting line").clone();
+        joined.extend(next_line);
+        editor.file.set_line(row as usize, joined, Action::Do);
+        editor.file.delete_line(row as usize + 1, Action::Do);
+        editor.cursor.history.update(CursorPosition::new(before, editor.cursor_at()), Action::Do);
     }
 
-    fn process_char(&mut self, editor: &mut Editor, col: u16, row: u16, c: char) {
+    fn process_char(&mut self, editor: &mut Editor, col: usize, row: u16, c: char) {
+        let before = (editor.file.start + row as usize, col);
+
         // If user types a closing literal that is preceeded by it's corresponding opening literal,
         // just move the cursor right
         if col != 0 {
             let line = editor.file.get_line(row as usize).expect("Buffer has no starting line");
-            let right_char = line.get(col as usize).copied();
+            let right_char = line.get(col).copied();
             
-            if let Some(right) = right_char {
-                if right == c && utils::openeable(c).is_some() {
-                    execute!(stdout(), MoveRight(1)).unwrap();
-                    editor.cursor.history.update(CursorPosition::new((col, row), Cursor::pos()), Action::Do);
-                    return;
-                }
+            if let Some(right) = right_char
+                && right == c
+                && utils::openeable(c).is_some()
+            {
+                editor.shift_cursor(1);
+                editor.cursor.history.update(CursorPosition::new(before, editor.cursor_at()), Action::Do);
+                return;
             }
         }
 
+        // A closing brace typed as the first char of an indented line takes away one level of indentation
+        let mut insert_col = col;
+        let line = editor.file.get_line(row as usize).expect("Buffer has no starting line");
+        if editor.settings.smartindent
+            && insert_col > 0
+            && utils::openeable(c).is_some_and(|opening| utils::braces(opening, c))
+            && line[..insert_col].iter().all(|c| *c == ' ' || *c == '\t')
+        {
+            let removed = Self::level_before(line, insert_col, editor.settings.shiftwidth);
+            let mut dedented = line[..insert_col - removed].to_vec();
+            dedented.extend(&line[insert_col..]);
+
+            editor.file.set_line(row as usize, dedented, Action::Do);
+            insert_col -= removed;
+        }
+
         // Otherwise, insert whatever the user types
-        editor.file.insert_char(row as usize, col as usize, c, Action::Do);
+        editor.file.insert_char(row as usize, insert_col, c, Action::Do);
         
-        execute!(stdout(), MoveRight(1)).unwrap();
+        editor.place_cursor((insert_col + 1, row));
         
         // If inserted char is a literal that is part of a pair, insert it's corresponding partner also
         if let Some(closing) = utils::closeable(c) {
-            editor.file.insert_char(row as usize, col as usize + 1, closing, Action::Do);
+            editor.file.insert_char(row as usize, insert_col + 1, closing, Action::Do);
         }
 
         // Update the cursor's history
-        editor.cursor.history.update(CursorPosition::new((col, row), Cursor::pos()), Action::Do);
+        editor.cursor.history.update(CursorPosition::new(before, editor.cursor_at()), Action::Do);
     }
 }
 
@@ -259,58 +1112,171 @@ impl ModeBehaviour for CommandMode {
     }
 
     fn listen(&mut self, editor: &mut Editor) {
+        self.prefilled(editor, "");
+    }
+}
+
+impl CommandMode {
+    // Reads a command with `input` already typed, like the range visual mode puts in
+    pub fn prefilled(&mut self, editor: &mut Editor, input: &str) {
+        let Some(command_str) = self.read_input(editor, ':', input) else {
+            return;
+        };
+
+        match command_str.parse::<Command>() {
+            Ok(command) => {
+                if let Err(e) = command.run(editor) {
+                    self.report(editor, e);
+                } 
+            }
+            Err(e) => self.report(editor, e),
+        }
+    }
+
+    // Reads "/pattern" and jumps to its next match
+    pub fn search(&mut self, editor: &mut Editor) {
+        let Some(pattern) = self.read_input(editor, '/', "") else {
+            return;
+        };
+
+        // An empty pattern searches for the last one again
+        if !pattern.is_empty() {
+            editor.search = Some(pattern);
+        }
+
+        if let Err(e) = editor.search_next(true) {
+            self.report(editor, e);
+        }
+    }
+
+    // Reads a line typed after `prompt` on the command row, starting from `input`. Returns None if
+    // it's backspaced away.
+    fn read_input(&mut self, editor: &mut Editor, prompt: char, input: &str) -> Option<String> {
+        let prev = Cursor::pos();
+
         editor.set_mode(Mode::Command);
         editor.cursor_command();
 
         utils::clear_line();
 
-        print_fg!(Color::DarkYellow, ":");
+        print_fg!(Color::DarkYellow, "{}{}", prompt, input);
         stdout().flush().unwrap();
 
-        let mut command_str = String::new();
+        let mut input = input.to_string();
+
+        // With incsearch, the view follows the first match of a search as it's typed
+        let mut preview = (prompt == '/' && editor.settings.incsearch).then(|| editor.begin_preview());
 
-        while let Some(key_event) = read().unwrap().as_key_event() {
+        loop {
+            let key_event = read_key();
             match key_event.code {
+                KeyCode::Char('w') if key_event.modifiers.contains(KeyModifiers::CONTROL) => {
+                    self.delete_word(&mut input);
+                    self.redraw(prompt, &input);
+                }
+                KeyCode::Char('u') if key_event.modifiers.contains(KeyModifiers::CONTROL) => {
+                    input.clear();
+                    self.redraw(prompt, &input);
+                }
                 KeyCode::Char(c) => {
                     print_fg!(Color::DarkYellow, "{}", c);
                     stdout().flush().unwrap();
-                    command_str.push(c);
+                    input.push(c);
                 }
-                KeyCode::Backspace => match command_str.pop() {
+                KeyCode::Backspace => match input.pop() {
                     Some(_) => {
                         execute!(stdout(), MoveLeft(1), Clear(ClearType::UntilNewLine)).unwrap()
                     }
                     None => {
+                        if let Some(preview) = preview.take() {
+                            editor.end_preview(preview);
+                            editor.cursor_command();
+                        }
                         utils::clear_line();
-                        break
+                        Cursor::move_to(prev);
+                        return None;
                     },
                 },
                 KeyCode::Enter => {
+                    if let Some(preview) = preview.take() {
+                        editor.end_preview(preview);
+                        editor.cursor_command();
+                    }
                     utils::clear_line();
-                    match command_str.parse::<Command>() {
-                        Ok(command) => {
-                            if let Err(e) = command.run(&mut editor.file) {
-                                utils::clear_line();
-                                print_bg!(Color::DarkRed, "{:?} - PRESS ANY KEY TO CONTINUE", e);
-                                execute!(stdout(), MoveToPreviousLine(1), MoveDown(1)).unwrap();
-
-                                // Press any key to continue
-                                read().unwrap();
-                            } 
-                        }
-                        Err(e) => {
-                            utils::clear_line();
-                            print_bg!(Color::DarkRed, "{:?} - PRESS ANY KEY TO CONTINUE", e);
 
-                            // Press any key to continue
-                            read().unwrap();
-                        },
-                    }
-                    execute!(stdout(), Clear(ClearType::CurrentLine)).unwrap();
-                    break;
+                    // Input is acted on from the normal mode cursor so any cursor movement it causes sticks
+                    Cursor::move_to(prev);
+                    return Some(input);
                 }
-                _ => {}
+                _ => continue,
+            }
+
+            // Drawing the match moves the cursor up into the buffer, so the prompt is put back after
+            if let Some(preview) = &preview {
+                editor.preview_search(&input, preview);
+                editor.cursor_command();
+                self.redraw(prompt, &input);
             }
         }
     }
+
+    fn redraw(&self, prompt: char, input: &str) {
+        utils::clear_line();
+        print_fg!(Color::DarkYellow, "{}{}", prompt, input);
+        stdout().flush().unwrap();
+    }
+
+    // Deletes back to the previous word boundary, skipping any trailing whitespace first
+    fn delete_word(&self, command_str: &mut String) {
+        let trimmed = command_str.trim_end();
+        let is_word = |c: char| c.is_alphanumeric() || c == '_';
+
+        let word_start = match trimmed.chars().last() {
+            Some(last) if is_word(last) => trimmed.trim_end_matches(is_word).len(),
+            Some(_) => trimmed.trim_end_matches(|c: char| !is_word(c) && !c.is_whitespace()).len(),
+            None => 0,
+        };
+        command_str.truncate(word_start);
+    }
+
+    fn report(&self, editor: &Editor, e: impl Debug) {
+        let prev = Cursor::pos();
+
+        editor.cursor_command();
+        utils::clear_line();
+        print_bg!(Color::DarkRed, "{:?} - PRESS ANY KEY TO CONTINUE", e);
+        execute!(stdout(), MoveToPreviousLine(1), MoveDown(1)).unwrap();
+
+        // Press any key to continue
+        read_key();
+
+        execute!(stdout(), Clear(ClearType::CurrentLine)).unwrap();
+        Cursor::move_to(prev);
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn delete_word(input: &str) -> String {
+        let mut input = input.to_string();
+        CommandMode.delete_word(&mut input);
+        input
+    }
+
+    #[test]
+    fn delete_word_goes_back_a_word_at_a_time() {
+        let mut input = "s/foo bar/baz qux".to_string();
+        for expected in ["s/foo bar/baz ", "s/foo bar/", "s/foo bar", "s/foo ", "s/", "s", ""] {
+            CommandMode.delete_word(&mut input);
+            assert_eq!(input, expected);
+        }
+    }
+
+    #[test]
+    fn delete_word_skips_trailing_spaces() {
+        assert_eq!(delete_word("set number   "), "set ");
+        assert_eq!(delete_word("e ../"), "e ");
+        assert_eq!(delete_word(""), "");
+    }
 }
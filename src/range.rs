@@ -0,0 +1,140 @@
+use crate::{Editor, RunError, Selection};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Address {
+    Line(usize),
+    Current,
+    Last,
+
+    // '< and '>, the first and last lines of the last visual selection
+    SelectionStart,
+    SelectionEnd,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Range {
+    pub start: Address,
+    pub end: Address,
+}
+
+impl Address {
+    fn parse(s: &str) -> Option<(Self, &str)> {
+        if let Some(rest) = s.strip_prefix('.') {
+            return Some((Self::Current, rest));
+        }
+        if let Some(rest) = s.strip_prefix('$') {
+            return Some((Self::Last, rest));
+        }
+        if let Some(rest) = s.strip_prefix("'<") {
+            return Some((Self::SelectionStart, rest));
+        }
+        if let Some(rest) = s.strip_prefix("'>") {
+            return Some((Self::SelectionEnd, rest));
+        }
+
+        let digits = s.len() - s.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+        let line = s[..digits].parse().ok()?;
+        Some((Self::Line(line), &s[digits..]))
+    }
+
+    // Addresses are 1-based like vim's, but resolve to an index into the buffer's lines
+    fn resolve(&self, editor: &Editor) -> Result<usize, RunError> {
+        // Finding the cursor asks the terminal, so it's only done when it's needed
+        let current = if *self == Self::Current { editor.cursor_at().0 } else { 0 };
+        self.line(current, editor.file.length(), editor.last_visual)
+    }
+
+    // The line index this is in a buffer `length` lines long, with the cursor on `current`
+    fn line(&self, current: usize, length: usize, selection: Option<Selection>) -> Result<usize, RunError> {
+        let line = match self {
+            Self::Line(line) => line.saturating_sub(1),
+            Self::Current => current,
+            Self::Last => length.saturating_sub(1),
+            Self::SelectionStart => *selection.ok_or(RunError::MarkNotSet)?.rows().start(),
+            Self::SelectionEnd => *selection.ok_or(RunError::MarkNotSet)?.rows().end(),
+        };
+
+        if line >= length {
+            return Err(RunError::InvalidRange);
+        }
+        Ok(line)
+    }
+
+    // Like resolve, but an address past the end lands on the last line instead of failing
+    pub fn resolve_clamped(&self, editor: &Editor) -> usize {
+        self.resolve(editor).unwrap_or(editor.file.length() - 1)
+    }
+}
+
+impl Range {
+    pub fn whole() -> Self {
+        Self { start: Address::Line(1), end: Address::Last }
+    }
+
+    pub fn current() -> Self {
+        Self { start: Address::Current, end: Address::Current }
+    }
+
+    // Splits a leading range like "10,20", "%" or "." off a command, returning what's left
+    pub fn parse(s: &str) -> (Option<Self>, &str) {
+        if let Some(rest) = s.strip_prefix('%') {
+            return (Some(Self::whole()), rest);
+        }
+
+        let Some((start, rest)) = Address::parse(s) else {
+            return (None, s);
+        };
+
+        if let Some(after_comma) = rest.strip_prefix(',')
+            && let Some((end, rest)) = Address::parse(after_comma)
+        {
+            return (Some(Self { start, end }), rest);
+        }
+
+        (Some(Self { start, end: start }), rest)
+    }
+
+    // Returns the first and last line indices covered by the range, inclusive
+    pub fn resolve(&self, editor: &Editor) -> Result<(usize, usize), RunError> {
+        let start = self.start.resolve(editor)?;
+        let end = self.end.resolve(editor)?;
+
+        if start > end {
+            return Err(RunError::InvalidRange);
+        }
+        Ok((start, end))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::SelectionKind;
+
+    #[test]
+    fn parse_splits_off_the_range() {
+        assert_eq!(Range::parse("10,20d"), (Some(Range { start: Address::Line(10), end: Address::Line(20) }), "d"));
+        assert_eq!(Range::parse("%s/a/b/"), (Some(Range::whole()), "s/a/b/"));
+        assert_eq!(Range::parse(".,$sort"), (Some(Range { start: Address::Current, end: Address::Last }), "sort"));
+        assert_eq!(Range::parse("'<,'>d"), (Some(Range { start: Address::SelectionStart, end: Address::SelectionEnd }), "d"));
+        assert_eq!(Range::parse("42"), (Some(Range { start: Address::Line(42), end: Address::Line(42) }), ""));
+        assert_eq!(Range::parse("w"), (None, "w"));
+    }
+
+    #[test]
+    fn addresses_resolve_to_line_indices() {
+        assert_eq!(Address::Line(1).line(3, 10, None).unwrap(), 0);
+        assert_eq!(Address::Line(0).line(3, 10, None).unwrap(), 0);
+        assert_eq!(Address::Current.line(3, 10, None).unwrap(), 3);
+        assert_eq!(Address::Last.line(3, 10, None).unwrap(), 9);
+        assert!(matches!(Address::Line(11).line(3, 10, None), Err(RunError::InvalidRange)));
+    }
+
+    #[test]
+    fn marks_come_from_the_last_selection() {
+        let selection = Selection { anchor: (6, 2), head: (4, 0), kind: SelectionKind::Char };
+        assert_eq!(Address::SelectionStart.line(0, 10, Some(selection)).unwrap(), 4);
+        assert_eq!(Address::SelectionEnd.line(0, 10, Some(selection)).unwrap(), 6);
+        assert!(matches!(Address::SelectionStart.line(0, 10, None), Err(RunError::MarkNotSet)));
+    }
+}
@@ -0,0 +1,51 @@
+use std::ops::{Range, RangeInclusive};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SelectionKind {
+    Char,
+    // Whole lines, whatever columns the anchor and head are on
+    Line,
+}
+
+// What visual mode has selected, from the anchor where it started to the head that follows the
+// cursor. Positions are absolute (row, col) pairs, and the head is part of the selection.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Selection {
+    pub anchor: (usize, usize),
+    pub head: (usize, usize),
+    pub kind: SelectionKind,
+}
+
+impl Selection {
+    pub fn new(pos: (usize, usize), kind: SelectionKind) -> Self {
+        Self { anchor: pos, head: pos, kind }
+    }
+
+    // The first and last selected positions, whichever way round the selection was made
+    pub fn bounds(&self) -> ((usize, usize), (usize, usize)) {
+        match self.anchor <= self.head {
+            true => (self.anchor, self.head),
+            false => (self.head, self.anchor),
+        }
+    }
+
+    pub fn rows(&self) -> RangeInclusive<usize> {
+        let (start, end) = self.bounds();
+        start.0..=end.0
+    }
+
+    // The columns selected on a row that is `len` chars long, if any
+    pub fn cols(&self, row: usize, len: usize) -> Option<Range<usize>> {
+        let ((start_row, start_col), (end_row, end_col)) = self.bounds();
+        if row < start_row || row > end_row {
+            return None;
+        }
+        if self.kind == SelectionKind::Line {
+            return Some(0..len);
+        }
+
+        let start = if row == start_row { start_col } else { 0 };
+        let end = if row == end_row { end_col + 1 } else { len };
+        Some(start..end)
+    }
+}
@@ -0,0 +1,267 @@
+use std::{cmp, error::Error, fmt, fs, io, path::Path, str::FromStr};
+
+use crate::{utils, Buffer, Editor, OpenError, Region, Window};
+
+// Bumped whenever the format changes so old sessions are rejected instead of misread
+const HEADER: &str = "vision-session 2";
+
+#[derive(Debug)]
+pub enum SessionError {
+    UnknownVersion,
+    InvalidLine,
+    IsDirectory(String),
+    Open(String, OpenError),
+}
+
+impl fmt::Display for SessionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::UnknownVersion => write!(f, "not a session file, or one from another version"),
+            Self::InvalidLine => write!(f, "session file is invalid"),
+            Self::IsDirectory(path) => write!(f, "{} is a directory", path),
+            Self::Open(path, e) => write!(f, "cannot open {}: {}", path, e),
+        }
+    }
+}
+
+impl Error for SessionError {}
+
+// An open buffer, with where it was scrolled to and where its cursor was
+#[derive(Debug, PartialEq)]
+struct View {
+    path: String,
+    start: usize,
+    cursor: (usize, u16),
+}
+
+// The window the cursor wasn't in, and which half of the screen the one it was in had
+#[derive(Debug, PartialEq)]
+struct Split {
+    region: Region,
+    buffer: usize,
+    start: usize,
+    cursor: (usize, u16),
+}
+
+pub struct Session {
+    buffers: Vec<View>,
+    current: Option<usize>,
+    split: Option<Split>,
+    options: Vec<String>,
+}
+
+impl FromStr for Session {
+    type Err = SessionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut lines = s.lines();
+        if lines.next() != Some(HEADER) {
+            return Err(SessionError::UnknownVersion);
+        }
+
+        let mut session = Session { buffers: vec![], current: None, split: None, options: vec![] };
+        for line in lines.filter(|line| !line.is_empty()) {
+            let (key, value) = line.split_once(' ').ok_or(SessionError::InvalidLine)?;
+            match key {
+                // The path goes last so it can have spaces in it
+                "buffer" => {
+                    let mut fields = value.splitn(4, ' ');
+                    let start = parse(fields.next())?;
+                    let cursor = (parse(fields.next())?, parse(fields.next())?);
+                    let path = fields.next().ok_or(SessionError::InvalidLine)?.to_string();
+                    session.buffers.push(View { path, start, cursor });
+                }
+                "current" => session.current = Some(parse(Some(value))?),
+                "split" => {
+                    let mut fields = value.split(' ');
+                    let region = match fields.next() {
+                        Some("top") => Region::Top,
+                        Some("bottom") => Region::Bottom,
+                        _ => return Err(SessionError::InvalidLine),
+                    };
+                    let buffer = parse(fields.next())?;
+                    let start = parse(fields.next())?;
+                    let cursor = (parse(fields.next())?, parse(fields.next())?);
+                    session.split = Some(Split { region, buffer, start, cursor });
+                }
+                "set" => session.options.push(value.to_string()),
+                _ => return Err(SessionError::InvalidLine),
+            }
+        }
+
+        // Buffers are referred to by where they are in the list, so those have to be in it
+        let count = session.buffers.len();
+        if session.current.is_some_and(|current| current >= count) || session.split.as_ref().is_some_and(|split| split.buffer >= count) {
+            return Err(SessionError::InvalidLine);
+        }
+
+        Ok(session)
+    }
+}
+
+impl fmt::Display for Session {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut lines = vec![HEADER.to_string()];
+        for option in &self.options {
+            lines.push(format!("set {}", option));
+        }
+        for view in &self.buffers {
+            lines.push(format!("buffer {} {} {} {}", view.start, view.cursor.0, view.cursor.1, view.path));
+        }
+        if let Some(current) = self.current {
+            lines.push(format!("current {}", current));
+        }
+        if let Some(split) = &self.split {
+            let region = if split.region == Region::Top { "top" } else { "bottom" };
+            lines.push(format!("split {} {} {} {} {}", region, split.buffer, split.start, split.cursor.0, split.cursor.1));
+        }
+
+        writeln!(f, "{}", lines.join("\n"))
+    }
+}
+
+fn parse<T: FromStr>(field: Option<&str>) -> Result<T, SessionError> {
+    field.and_then(|field| field.parse().ok()).ok_or(SessionError::InvalidLine)
+}
+
+impl Session {
+    pub fn capture(editor: &Editor) -> Self {
+        // Unnamed and scratch buffers can't be reopened, so they're left out and the rest are
+        // renumbered without them
+        let mut buffers = vec![];
+        let mut saved = vec![];
+        for (i, buffer) in editor.buffers().enumerate() {
+            match &buffer.path {
+                Some(path) if !buffer.scratch => {
+                    saved.push(Some(buffers.len()));
+                    buffers.push(View { path: path.clone(), start: buffer.start, cursor: editor.buffer_cursor(i) });
+                }
+                _ => saved.push(None),
+            }
+        }
+
+        let split = editor.split().and_then(|window| {
+            Some(Split { region: utils::region(), buffer: saved[window.buffer]?, start: window.start, cursor: window.pos })
+        });
+
+        Self {
+            buffers,
+            current: saved[editor.buffer_index()],
+            split,
+            options: editor.settings.options(),
+        }
+    }
+
+    pub fn write(&self, path: &str) -> Result<(), io::Error> {
+        fs::write(path, self.to_string())
+    }
+
+    pub fn restore(&self) -> Result<Editor, SessionError> {
+        // Every file is opened before the editor takes over the screen, so failing can still be
+        // reported on the terminal as it was
+        let mut buffers = vec![];
+        for view in &self.buffers {
+            let path = Path::new(&view.path);
+            if path.is_dir() {
+                return Err(SessionError::IsDirectory(view.path.clone()));
+            }
+
+            buffers.push(match path.is_file() {
+                true => view.path.parse::<Buffer>().map_err(|e| SessionError::Open(view.path.clone(), e))?,
+                false => Buffer::new(Some(view.path.clone())),
+            });
+        }
+
+        let mut buffers = buffers.into_iter();
+        let mut editor = Editor::new(buffers.next().unwrap_or_default());
+
+        // Options from a newer version that this one doesn't know about are skipped
+        for option in &self.options {
+            let _ = editor.settings.set(option);
+        }
+        editor.apply_settings();
+        editor.render();
+
+        for (i, view) in self.buffers.iter().enumerate() {
+            if i > 0 {
+                editor.add_buffer(buffers.next().unwrap_or_default());
+            }
+            show(&mut editor, view.start, view.cursor);
+        }
+
+        if let Some(current) = self.current {
+            editor.switch_buffer(current);
+        }
+        if let Some(split) = &self.split {
+            let window = Window { buffer: split.buffer, start: split.start, left: 0, pos: split.cursor };
+            editor.open_split(window, split.region);
+        }
+
+        Ok(editor)
+    }
+}
+
+// Scrolls the current buffer and places its cursor as saved, clamped in case the file has shrunk
+fn show(editor: &mut Editor, start: usize, cursor: (usize, u16)) {
+    let last = editor.file.length() - 1;
+    editor.file.start = cmp::min(start, last);
+
+    let row = cmp::min(cursor.1 as usize, last - editor.file.start);
+    let len = editor.file.get_line(row).map_or(0, |line| line.len());
+    let col = cmp::min(cursor.0, len);
+
+    editor.place_cursor((col, row as u16));
+    editor.redraw();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session() -> Session {
+        Session {
+            buffers: vec![
+                View { path: "src/main.rs".to_string(), start: 10, cursor: (4, 2) },
+                View { path: "notes with spaces.txt".to_string(), start: 0, cursor: (0, 7) },
+            ],
+            current: Some(1),
+            split: Some(Split { region: Region::Bottom, buffer: 0, start: 3, cursor: (1, 5) }),
+            options: vec!["number".to_string(), "shiftwidth=2".to_string()],
+        }
+    }
+
+    #[test]
+    fn written_session_reads_back_the_same() {
+        let written = session().to_string();
+        let read = written.parse::<Session>().unwrap();
+
+        assert_eq!(read.buffers, session().buffers);
+        assert_eq!(read.current, Some(1));
+        assert_eq!(read.split, session().split);
+        assert_eq!(read.options, session().options);
+        assert_eq!(read.to_string(), written);
+    }
+
+    #[test]
+    fn other_versions_are_rejected() {
+        let written = session().to_string().replace(HEADER, "vision-session 1");
+        assert!(matches!(written.parse::<Session>(), Err(SessionError::UnknownVersion)));
+    }
+
+    #[test]
+    fn indices_past_the_buffers_are_rejected() {
+        let current = format!("{}\nbuffer 0 0 0 a.txt\ncurrent 1\n", HEADER);
+        assert!(matches!(current.parse::<Session>(), Err(SessionError::InvalidLine)));
+
+        let split = format!("{}\nbuffer 0 0 0 a.txt\nsplit top 2 0 0 0\n", HEADER);
+        assert!(matches!(split.parse::<Session>(), Err(SessionError::InvalidLine)));
+    }
+
+    #[test]
+    fn malformed_lines_are_rejected() {
+        for line in ["buffer 0 0 a.txt", "split left 0 0 0 0", "current x", "window 1"] {
+            let session = format!("{}\nbuffer 0 0 0 a.txt\n{}\n", HEADER, line);
+            assert!(matches!(session.parse::<Session>(), Err(SessionError::InvalidLine)), "{}", line);
+        }
+    }
+}
@@ -0,0 +1,220 @@
+use std::{ops::RangeInclusive, str::FromStr};
+
+use crate::RunError;
+
+pub struct Settings {
+    pub ruler: bool,
+    pub changecount: bool,
+    pub smartindent: bool,
+    pub showindentlevel: bool,
+    pub number: bool,
+
+    // Keys typed so far of a command not yet finished are shown at the right of the mode line,
+    // or the size of the selection in visual mode
+    pub showcmd: bool,
+
+    // Long lines carry on onto the rows below instead of scrolling sideways
+    pub wrap: bool,
+
+    // New splits take the bottom half for the cursor instead of the top
+    pub splitbelow: bool,
+    pub shiftwidth: usize,
+    pub tabstop: usize,
+    pub expandtab: bool,
+    pub ignorecase: bool,
+
+    // With ignorecase, a pattern with any uppercase in it is still matched exactly
+    pub smartcase: bool,
+
+    // The view jumps to the first match of a search while it's still being typed
+    pub incsearch: bool,
+    pub undolevels: i64,
+    pub conceallevel: u8,
+
+    // Yanks and puts go through the system clipboard, for clipboard=unnamed
+    pub clipboard: bool,
+
+    // Lines kept in view above and below the cursor when scrolling
+    pub scrolloff: usize,
+
+    // Commands that change more lines than this say how many they did
+    pub report: usize,
+
+    // Seconds without a keypress before the buffer is saved on its own, None to never do it
+    pub autosave: Option<u64>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            ruler: false,
+            changecount: false,
+            smartindent: false,
+            showindentlevel: false,
+            number: false,
+            showcmd: true,
+            wrap: false,
+            splitbelow: false,
+            shiftwidth: 4,
+            tabstop: 4,
+            expandtab: true,
+            ignorecase: false,
+            smartcase: false,
+            incsearch: false,
+            undolevels: 1000,
+            conceallevel: 0,
+            clipboard: false,
+            scrolloff: 0,
+            report: 2,
+            autosave: None,
+        }
+    }
+}
+
+impl Settings {
+    pub fn set(&mut self, option: &str) -> Result<(), RunError> {
+        if let Some((name, value)) = option.split_once('=') {
+            return self.set_value(name, value);
+        }
+
+        // Boolean options are turned off by prefixing them with "no", e.g. "noruler"
+        let enabled = !option.starts_with("no");
+
+        match option {
+            "ruler" | "noruler" => self.ruler = enabled,
+            "changecount" | "nochangecount" => self.changecount = enabled,
+            "smartindent" | "nosmartindent" => self.smartindent = enabled,
+            "showindentlevel" | "noshowindentlevel" => self.showindentlevel = enabled,
+            "number" | "nonumber" => self.number = enabled,
+            "showcmd" | "noshowcmd" => self.showcmd = enabled,
+            "wrap" | "nowrap" => self.wrap = enabled,
+            "splitbelow" | "nosplitbelow" => self.splitbelow = enabled,
+            "expandtab" | "noexpandtab" => self.expandtab = enabled,
+            "ignorecase" | "noignorecase" => self.ignorecase = enabled,
+            "smartcase" | "nosmartcase" => self.smartcase = enabled,
+            "incsearch" | "noincsearch" => self.incsearch = enabled,
+            "autosave" => self.autosave = Some(30),
+            "noautosave" => self.autosave = None,
+            _ => return Err(RunError::UnknownOption),
+        }
+
+        Ok(())
+    }
+
+    // Whether searching for `pattern` ignores case, going by ignorecase and smartcase
+    pub fn ignore_case(&self, pattern: &[char]) -> bool {
+        self.ignorecase && !(self.smartcase && pattern.iter().any(|c| c.is_uppercase()))
+    }
+
+    // One level of indentation, a tab unless expandtab turns it into shiftwidth spaces
+    pub fn indent(&self) -> Vec<char> {
+        match self.expandtab {
+            true => vec![' '; self.shiftwidth],
+            false => vec!['\t'],
+        }
+    }
+
+    // Every option written the way :set takes it, so a saved session can replay them
+    pub fn options(&self) -> Vec<String> {
+        let flag = |name: &str, enabled: bool| match enabled {
+            true => name.to_string(),
+            false => format!("no{}", name),
+        };
+
+        vec![
+            flag("ruler", self.ruler),
+            flag("changecount", self.changecount),
+            flag("smartindent", self.smartindent),
+            flag("showindentlevel", self.showindentlevel),
+            flag("number", self.number),
+            flag("showcmd", self.showcmd),
+            flag("wrap", self.wrap),
+            flag("splitbelow", self.splitbelow),
+            flag("expandtab", self.expandtab),
+            flag("ignorecase", self.ignorecase),
+            flag("smartcase", self.smartcase),
+            flag("incsearch", self.incsearch),
+            format!("shiftwidth={}", self.shiftwidth),
+            format!("tabstop={}", self.tabstop),
+            format!("undolevels={}", self.undolevels),
+            format!("conceallevel={}", self.conceallevel),
+            format!("clipboard={}", if self.clipboard { "unnamed" } else { "" }),
+            format!("scrolloff={}", self.scrolloff),
+            format!("report={}", self.report),
+            match self.autosave {
+                Some(seconds) => format!("autosave={}", seconds),
+                None => "noautosave".to_string(),
+            },
+        ]
+    }
+
+    fn set_value(&mut self, name: &str, value: &str) -> Result<(), RunError> {
+        match name {
+            "shiftwidth" => self.shiftwidth = parse(value, 1..=16)?,
+            "tabstop" => self.tabstop = parse(value, 1..=32)?,
+            "undolevels" => self.undolevels = parse(value, i64::MIN..=i64::MAX)?,
+            "conceallevel" => self.conceallevel = parse(value, 0..=3)?,
+            "clipboard" => self.clipboard = match value {
+                "unnamed" | "unnamedplus" => true,
+                "" => false,
+                _ => return Err(RunError::InvalidValue),
+            },
+            "scrolloff" => self.scrolloff = parse(value, 0..=999)?,
+            "report" => self.report = parse(value, 0..=usize::MAX)?,
+            "autosave" => self.autosave = Some(parse(value, 1..=86400)?),
+            _ => return Err(RunError::UnknownOption),
+        }
+
+        Ok(())
+    }
+}
+
+fn parse<T: FromStr + PartialOrd>(value: &str, valid: RangeInclusive<T>) -> Result<T, RunError> {
+    match value.parse::<T>() {
+        Ok(value) if valid.contains(&value) => Ok(value),
+        _ => Err(RunError::InvalidValue),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils;
+
+    fn chars(text: &str) -> Vec<char> {
+        text.chars().collect()
+    }
+
+    fn find(settings: &Settings, line: &str, pattern: &str) -> Option<usize> {
+        let pattern = chars(pattern);
+        utils::find_chars(&chars(line), &pattern, 0, settings.ignore_case(&pattern))
+    }
+
+    #[test]
+    fn ignorecase_matches_mixed_case() {
+        let mut settings = Settings::default();
+        assert_eq!(find(&settings, "say Hello", "hello"), None);
+
+        settings.set("ignorecase").unwrap();
+        assert_eq!(find(&settings, "say Hello", "hello"), Some(4));
+        assert_eq!(find(&settings, "say Hello", "HELLO"), Some(4));
+    }
+
+    #[test]
+    fn smartcase_keeps_uppercase_patterns_exact() {
+        let mut settings = Settings::default();
+        settings.set("ignorecase").unwrap();
+        settings.set("smartcase").unwrap();
+
+        assert_eq!(find(&settings, "say hello", "Hello"), None);
+        assert_eq!(find(&settings, "say hello Hello", "Hello"), Some(10));
+        assert_eq!(find(&settings, "say Hello", "hello"), Some(4));
+    }
+
+    #[test]
+    fn smartcase_needs_ignorecase() {
+        let mut settings = Settings::default();
+        settings.set("smartcase").unwrap();
+        assert!(!settings.ignore_case(&chars("hello")));
+    }
+}
@@ -1,5 +1,7 @@
-use std::{collections::HashMap, io::stdout};
-use crossterm::{cursor::{position, MoveTo}, execute, style::{Color, Print, ResetColor, SetBackgroundColor, SetForegroundColor}, terminal::{size, Clear, ClearType}};
+use std::{io::stdout, sync::atomic::{AtomicU8, Ordering}};
+use crossterm::{cursor::{position, MoveTo}, execute, terminal::{size, Clear, ClearType}};
+
+use crate::Cursor;
 
 #[macro_export]
 macro_rules! print_bg {
@@ -33,8 +35,169 @@ pub fn clear_line() {
     ).unwrap();
 }
 
+// Cuts text down to `width` chars by dropping its start, marking the cut with an ellipsis
+pub fn truncate_left(text: &str, width: usize) -> String {
+    let len = text.chars().count();
+    if len <= width {
+        return text.to_string();
+    }
+    if width == 0 {
+        return String::new();
+    }
+
+    let kept: String = text.chars().skip(len - (width - 1)).collect();
+    format!("…{}", kept)
+}
+
+// Columns left for text once the gutter is taken out
+pub fn text_width() -> u16 {
+    size().unwrap().0.saturating_sub(Cursor::gutter())
+}
+
+// Part of the screen a window takes up. Only one split is supported, so there are at most two.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Region {
+    Full,
+    Top,
+    Bottom,
+}
+
+impl Region {
+    // The first screen row and how many rows of text there are. A split gives the top window the
+    // smaller half, with a row under it for its status line.
+    pub fn bounds(&self) -> (u16, u16) {
+        let rows = size().unwrap().1.saturating_sub(1);
+        let top = rows.saturating_sub(1) / 2;
+        match self {
+            Self::Full => (0, rows),
+            Self::Top => (0, top),
+            Self::Bottom => (top + 1, rows.saturating_sub(top + 1)),
+        }
+    }
+}
+
+// The region of the window being edited or drawn, which screen rows are counted from
+static REGION: AtomicU8 = AtomicU8::new(0);
+
+pub fn region() -> Region {
+    match REGION.load(Ordering::Relaxed) {
+        1 => Region::Top,
+        2 => Region::Bottom,
+        _ => Region::Full,
+    }
+}
+
+pub fn set_region(region: Region) {
+    REGION.store(region as u8, Ordering::Relaxed);
+}
+
+// Screen row the current window starts on
+pub fn window_top() -> u16 {
+    region().bounds().0
+}
+
+// Last row of text in the current window, counted from its top
 pub fn window_size() -> u16 {
-    size().unwrap().1 - 2
+    region().bounds().1.saturating_sub(1)
+}
+
+// The mode line, at the bottom of the screen however it's split
+pub fn status_row() -> u16 {
+    size().unwrap().1.saturating_sub(1)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CharClass {
+    Whitespace,
+    Word,
+    Punctuation,
+}
+
+// Word motions treat runs of the same class as one word
+pub fn char_class(c: char) -> CharClass {
+    if c.is_whitespace() {
+        CharClass::Whitespace
+    } else if c.is_alphanumeric() || c == '_' {
+        CharClass::Word
+    } else {
+        CharClass::Punctuation
+    }
+}
+
+// Screen column a char index is drawn at, with tabs expanded to the next tab stop
+pub fn visual_col(line: &[char], col: usize, tabstop: usize) -> usize {
+    let mut visual = 0;
+    for (i, c) in line.iter().enumerate() {
+        if i == col {
+            return visual;
+        }
+        visual += match c {
+            '\t' => tabstop - visual % tabstop,
+            _ => 1,
+        };
+    }
+
+    // Past the end of the line every column is one char wide
+    visual + col.saturating_sub(line.len())
+}
+
+// How far the view has to be scrolled sideways, from `left`, for screen column `visual` to be in
+// a window `width` columns wide. It only moves as far as it has to, in either direction.
+pub fn follow_col(left: usize, visual: usize, width: usize) -> usize {
+    if visual < left {
+        visual
+    } else if visual >= left + width {
+        visual + 1 - width
+    } else {
+        left
+    }
+}
+
+// Index of the char drawn at a screen column, the opposite of visual_col
+pub fn logical_col(line: &[char], visual: usize, tabstop: usize) -> usize {
+    let mut start = 0;
+    for (i, c) in line.iter().enumerate() {
+        let end = start + match c {
+            '\t' => tabstop - start % tabstop,
+            _ => 1,
+        };
+        if visual < end {
+            return i;
+        }
+        start = end;
+    }
+
+    line.len() + (visual - start)
+}
+
+// Column of the first char that isn't a space or tab, or the line length if there is none
+pub fn first_non_blank(line: &[char]) -> usize {
+    line.iter().position(|c| *c != ' ' && *c != '\t').unwrap_or(line.len())
+}
+
+// Column of the first match of `pattern` in `line` that starts at or after `from`
+pub fn find_chars(line: &[char], pattern: &[char], from: usize, ignore_case: bool) -> Option<usize> {
+    if pattern.is_empty() || pattern.len() > line.len() {
+        return None;
+    }
+    (from..=line.len() - pattern.len()).find(|&i| matches_at(line, pattern, i, ignore_case))
+}
+
+// Column of the last match of `pattern` in `line` that starts before `before`
+pub fn rfind_chars(line: &[char], pattern: &[char], before: usize, ignore_case: bool) -> Option<usize> {
+    if pattern.is_empty() || pattern.len() > line.len() {
+        return None;
+    }
+    (0..=line.len() - pattern.len()).rev().find(|&i| i < before && matches_at(line, pattern, i, ignore_case))
+}
+
+// Chars are compared one for one even ignoring case, so a match is always as long as the pattern
+fn matches_at(line: &[char], pattern: &[char], at: usize, ignore_case: bool) -> bool {
+    let fold = |c: char| c.to_lowercase().next().unwrap_or(c);
+    line[at..at + pattern.len()]
+        .iter()
+        .zip(pattern)
+        .all(|(a, b)| a == b || (ignore_case && fold(*a) == fold(*b)))
 }
 
 pub fn closeable(c1: char) -> Option<char> {
@@ -68,4 +231,29 @@ pub fn braces(opening: char, closing: char) -> bool {
 
 pub fn pair(c1: char, c2: char) -> bool {
     braces(c1, c2) || matches!((c1, c2), ('\'', '\'') | ('"', '"') | ('`', '`'))
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Typing runs through place_cursor one char at a time, insert mode included, so the caret
+    // has to stay in view at every step of a line much longer than the window
+    #[test]
+    fn typing_a_long_line_keeps_the_caret_in_view() {
+        let width = 80;
+        let mut left = 0;
+        for visual in 0..=300 {
+            left = follow_col(left, visual, width);
+            assert!(left <= visual && visual < left + width);
+        }
+        assert_eq!(left, 221);
+
+        // And backspacing all the way scrolls back to the start
+        for visual in (0..=300).rev() {
+            left = follow_col(left, visual, width);
+            assert!(left <= visual && visual < left + width);
+        }
+        assert_eq!(left, 0);
+    }
+}